@@ -0,0 +1,157 @@
+use crate::{xml, JsonObject};
+use futures::stream;
+use serde_json::Value as JsonValue;
+use std::convert::Infallible;
+use warp::{
+    http::{header::CONTENT_TYPE, HeaderMap},
+    hyper::body::Bytes,
+};
+
+/// Parses an incoming body according to its `Content-Type`, which defaults to JSON
+pub async fn parse(headers: &HeaderMap, body: &Bytes) -> Result<JsonValue, String> {
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .map(|v| v.to_str().map_err(|e| e.to_string()))
+        .transpose()?
+        .unwrap_or("application/json");
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    match essence.as_str() {
+        "application/json" => serde_json::from_slice(body).map_err(|e| e.to_string()),
+        s if s.ends_with("+json") => serde_json::from_slice(body).map_err(|e| e.to_string()),
+        "application/x-www-form-urlencoded" => {
+            let pairs: Vec<(String, String)> =
+                serde_urlencoded::from_bytes(body).map_err(|e| e.to_string())?;
+            let mut form = JsonObject::new();
+            for (name, value) in pairs {
+                insert(&mut form, name, field(value));
+            }
+            Ok(form.into())
+        }
+        "multipart/form-data" => multipart(content_type, body).await,
+        "application/xml" | "text/xml" => xml::parse(body),
+        s if s.ends_with("+xml") => xml::parse(body),
+        s if s.starts_with("text/") => String::from_utf8(body.to_vec())
+            .map(JsonValue::from)
+            .map_err(|e| e.to_string()),
+        _ => Err(format!("unsupported content type `{}`", content_type)),
+    }
+}
+
+/// Parses the fields of a multipart form, files becoming objects with their name, content type
+/// and base64 encoded content
+async fn multipart(content_type: &str, body: &Bytes) -> Result<JsonValue, String> {
+    let boundary = multer::parse_boundary(content_type).map_err(|e| e.to_string())?;
+    let body = body.to_vec();
+    let mut parts = multer::Multipart::new(
+        stream::once(async move { Ok::<_, Infallible>(body) }),
+        boundary,
+    );
+
+    let mut form = JsonObject::new();
+    while let Some(part) = parts.next_field().await.map_err(|e| e.to_string())? {
+        let name = part.name().unwrap_or_default().to_owned();
+        let value = match (part.file_name(), part.content_type()) {
+            (Some(filename), content_type) => {
+                let mut file = JsonObject::new();
+                file.insert("filename".to_owned(), filename.into());
+                file.insert(
+                    "contentType".to_owned(),
+                    content_type.map(|m| m.to_string()).into(),
+                );
+                let content = part.bytes().await.map_err(|e| e.to_string())?;
+                file.insert("content".to_owned(), base64::encode(content).into());
+                file.into()
+            }
+            (None, _) => field(part.text().await.map_err(|e| e.to_string())?),
+        };
+        insert(&mut form, name, value);
+    }
+    Ok(form.into())
+}
+
+/// Turns a form field into JSON, parsing embedded JSON objects like the `payload` field of Slack
+fn field(value: String) -> JsonValue {
+    if value.trim_start().starts_with('{') {
+        if let Ok(object @ JsonValue::Object(_)) = serde_json::from_str(&value) {
+            return object;
+        }
+    }
+    value.into()
+}
+
+/// Inserts a form field or XML element, collecting the values of repeated ones in an array
+pub fn insert(form: &mut JsonObject, name: String, value: JsonValue) {
+    match form.get_mut(&name) {
+        Some(JsonValue::Array(values)) => values.push(value),
+        Some(existing) => *existing = vec![existing.take(), value].into(),
+        None => {
+            form.insert(name, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use warp::http::HeaderValue;
+
+    async fn parsed(content_type: &str, body: &str) -> Result<JsonValue, String> {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_str(content_type).unwrap());
+        parse(&headers, &Bytes::from(body.to_owned())).await
+    }
+
+    #[tokio::test]
+    async fn forms() {
+        assert_eq!(
+            parsed(
+                "application/x-www-form-urlencoded",
+                "a=1&b=x+y&a=2&a=3&payload=%7B%22c%22%3A%5B1%5D%7D&d=%7Bnot+json"
+            )
+            .await,
+            Ok(
+                json!({ "a": ["1", "2", "3"], "b": "x y", "payload": { "c": [1] }, "d": "{not json" })
+            )
+        );
+
+        let multipart = "--X\r\n\
+            Content-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n\
+            --X\r\n\
+            Content-Disposition: form-data; name=\"a\"\r\n\r\n {\"b\": 2}\r\n\
+            --X\r\n\
+            Content-Disposition: form-data; name=\"f\"; filename=\"f.txt\"\r\n\
+            Content-Type: text/plain\r\n\r\nhi\r\n\
+            --X--\r\n";
+        assert_eq!(
+            parsed("multipart/form-data; boundary=X", multipart).await,
+            Ok(json!({
+                "a": ["1", { "b": 2 }],
+                "f": { "filename": "f.txt", "contentType": "text/plain", "content": "aGk=" },
+            }))
+        );
+    }
+
+    #[tokio::test]
+    async fn content_types() {
+        assert_eq!(
+            parse(&HeaderMap::new(), &Bytes::from_static(b"[1]")).await,
+            Ok(json!([1]))
+        );
+        assert_eq!(
+            parsed("application/vnd.api+json; charset=utf-8", "{}").await,
+            Ok(json!({}))
+        );
+        assert_eq!(parsed("Text/Plain", "hi").await, Ok(json!("hi")));
+        assert_eq!(
+            parsed("image/png", "").await,
+            Err("unsupported content type `image/png`".to_owned())
+        );
+    }
+}
@@ -0,0 +1,204 @@
+use crate::{
+    path,
+    request::{Request, Source},
+    resolve,
+    template::display,
+    JsonPath,
+};
+use regex::Regex;
+use serde::{de::Error as _, Deserialize, Deserializer};
+use serde_json::Value as JsonValue;
+use std::{cmp::Ordering, convert::TryFrom};
+
+/// A condition on the incoming request, either a test of a value or a combination of conditions
+#[derive(Deserialize)]
+#[serde(try_from = "JsonValue")]
+pub enum Condition {
+    All(Vec<Condition>),
+    Any(Vec<Condition>),
+    Not(Box<Condition>),
+    Test(Box<Test>),
+}
+
+/// Tests a value of the request, holding when every operator given holds
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Test {
+    #[serde(default)]
+    source: Source,
+    #[serde(deserialize_with = "path::deserialize")]
+    path: JsonPath,
+    exists: Option<bool>,
+    equals: Option<JsonValue>,
+    not_equals: Option<JsonValue>,
+    #[serde(default, deserialize_with = "regex")]
+    matches: Option<Regex>,
+    lt: Option<JsonValue>,
+    lte: Option<JsonValue>,
+    gt: Option<JsonValue>,
+    gte: Option<JsonValue>,
+}
+
+fn regex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Regex>, D::Error> {
+    Regex::new(&String::deserialize(deserializer)?)
+        .map(Some)
+        .map_err(D::Error::custom)
+}
+
+impl TryFrom<JsonValue> for Condition {
+    type Error = String;
+
+    fn try_from(value: JsonValue) -> Result<Self, Self::Error> {
+        fn conditions(value: JsonValue) -> Result<Vec<Condition>, String> {
+            serde_json::from_value(value).map_err(|e| e.to_string())
+        }
+
+        // A list is shorthand for `all`
+        let mut object = match value {
+            JsonValue::Array(_) => return conditions(value).map(Condition::All),
+            JsonValue::Object(o) => o,
+            v => return Err(format!("expected a condition, got `{}`", v)),
+        };
+        if object.len() == 1 {
+            if let Some(all) = object.remove("all") {
+                return conditions(all).map(Condition::All);
+            } else if let Some(any) = object.remove("any") {
+                return conditions(any).map(Condition::Any);
+            } else if let Some(not) = object.remove("not") {
+                return serde_json::from_value(not)
+                    .map(|c| Condition::Not(Box::new(c)))
+                    .map_err(|e| e.to_string());
+            }
+        }
+        serde_json::from_value(object.into())
+            .map(|t| Condition::Test(Box::new(t)))
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl Condition {
+    pub fn matches(&self, request: &Request) -> bool {
+        match self {
+            Condition::All(conditions) => conditions.iter().all(|c| c.matches(request)),
+            Condition::Any(conditions) => conditions.iter().any(|c| c.matches(request)),
+            Condition::Not(condition) => !condition.matches(request),
+            Condition::Test(test) => test.matches(request),
+        }
+    }
+}
+
+impl Test {
+    fn matches(&self, request: &Request) -> bool {
+        let value = match resolve(request.root(self.source), &self.path) {
+            Ok(v) => v,
+            // Without a value, only `exists: false` and `notEquals` can hold, and a test without
+            // operators checks that the value exists
+            Err(_) => {
+                let comparisons = [&self.equals, &self.lt, &self.lte, &self.gt, &self.gte];
+                return self.exists != Some(true)
+                    && comparisons.iter().all(|c| c.is_none())
+                    && self.matches.is_none()
+                    && (self.exists.is_some() || self.not_equals.is_some());
+            }
+        };
+        let ordering = |other: &Option<JsonValue>, holds: fn(Ordering) -> bool| {
+            other
+                .as_ref()
+                .is_none_or(|o| compare(&value, o).is_some_and(holds))
+        };
+
+        self.exists != Some(false)
+            && ordering(&self.equals, Ordering::is_eq)
+            && self
+                .not_equals
+                .as_ref()
+                .is_none_or(|o| !compare(&value, o).is_some_and(Ordering::is_eq))
+            && self
+                .matches
+                .as_ref()
+                .is_none_or(|r| r.is_match(&display(&value)))
+            && ordering(&self.lt, Ordering::is_lt)
+            && ordering(&self.lte, Ordering::is_le)
+            && ordering(&self.gt, Ordering::is_gt)
+            && ordering(&self.gte, Ordering::is_ge)
+    }
+}
+
+/// Compares numbers by value and strings alphabetically, other values can only be equal
+pub fn compare(a: &JsonValue, b: &JsonValue) -> Option<Ordering> {
+    match (a, b) {
+        (JsonValue::Number(a), JsonValue::Number(b)) => a.as_f64().partial_cmp(&b.as_f64()),
+        (JsonValue::String(a), JsonValue::String(b)) => Some(a.cmp(b)),
+        (a, b) if a == b => Some(Ordering::Equal),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn holds(condition: JsonValue) -> bool {
+        let request = Request {
+            body: json!({ "action": "opened", "count": 3, "empty": null }),
+            headers: json!({ "x-event": "issues" }),
+            query: json!({}),
+            path: json!([]),
+            meta: json!({}),
+        };
+        Condition::try_from(condition).unwrap().matches(&request)
+    }
+
+    #[test]
+    fn missing_values() {
+        assert!(!holds(json!({ "path": "/missing" })));
+        assert!(holds(json!({ "path": "/missing", "exists": false })));
+        assert!(!holds(json!({ "path": "/missing", "exists": true })));
+        assert!(holds(json!({ "path": "/missing", "notEquals": 1 })));
+        assert!(!holds(
+            json!({ "path": "/missing", "notEquals": 1, "exists": true })
+        ));
+        assert!(!holds(
+            json!({ "path": "/missing", "notEquals": 1, "lt": 2 })
+        ));
+        assert!(!holds(
+            json!({ "path": "/missing", "exists": false, "matches": "" })
+        ));
+        assert!(!holds(json!({ "path": "/missing", "equals": null })));
+
+        assert!(holds(json!({ "path": "/empty" })));
+        assert!(holds(json!({ "path": "/empty", "equals": null })));
+        assert!(!holds(json!({ "path": "/empty", "exists": false })));
+    }
+
+    #[test]
+    fn combinations() {
+        assert!(holds(json!([
+            { "path": "/action", "equals": "opened" },
+            { "source": "header", "path": "/x-event", "matches": "^iss" },
+        ])));
+        assert!(holds(json!({ "any": [
+            { "path": "/count", "gt": 5 },
+            { "path": "/count", "gte": 3, "lt": 4 },
+        ] })));
+        assert!(holds(json!({ "not": { "all": [
+            { "path": "/count", "lte": 3 },
+            { "path": "/action", "notEquals": "opened" },
+        ] } })));
+        assert!(holds(json!({ "all": [] })));
+        assert!(!holds(json!({ "any": [] })));
+    }
+
+    #[test]
+    fn invalid() {
+        let error = |condition| Condition::try_from(condition).err();
+        assert_eq!(
+            error(json!("/action")),
+            Some("expected a condition, got `\"/action\"`".to_owned())
+        );
+        assert!(error(json!({ "path": "/action", "equal": 1 })).is_some());
+        assert!(error(json!({ "all": [], "path": "/action" })).is_some());
+        assert!(error(json!({ "path": "/action", "matches": "(" })).is_some());
+    }
+}
@@ -0,0 +1,321 @@
+use crate::{
+    forward,
+    queue::{self, Job, Queue},
+    Config, JsonObject, TryExt,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use subtle::ConstantTimeEq;
+use tokio::fs;
+use warp::{
+    http::{Method, StatusCode},
+    path::Tail,
+    reply::Response,
+    Rejection, Reply,
+};
+
+/// A delivery which failed permanently
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetter {
+    pub webhook: String,
+    pub url: String,
+    pub body: String,
+    pub payload: JsonObject,
+    pub error: String,
+    pub attempts: u32,
+    /// Unix timestamp of the last attempt
+    pub failed_at: u64,
+}
+
+impl DeadLetter {
+    pub fn new(job: Job, error: String, attempts: u32) -> Self {
+        Self {
+            webhook: job.webhook,
+            url: job.url,
+            body: job.body,
+            payload: job.payload,
+            error,
+            attempts,
+            failed_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        }
+    }
+
+    fn into_job(self) -> Job {
+        Job {
+            webhook: self.webhook,
+            url: self.url,
+            body: self.body,
+            payload: self.payload,
+        }
+    }
+}
+
+/// Dead letter store, keeping one file per failed delivery
+pub struct DeadLetters {
+    dir: PathBuf,
+}
+
+impl DeadLetters {
+    pub async fn open(data_dir: &Path) -> io::Result<Self> {
+        let dir = data_dir.join("dead");
+        fs::create_dir_all(&dir).await?;
+        Ok(Self { dir })
+    }
+
+    /// Stores a dead letter and returns its id
+    pub async fn push(&self, letter: &DeadLetter) -> io::Result<String> {
+        let id = queue::unique_name();
+        queue::write(
+            &self.dir.join(format!("{}.json", id)),
+            &serde_json::to_vec(letter)?,
+        )
+        .await?;
+        Ok(id)
+    }
+
+    /// Lists the ids of the stored dead letters, oldest first
+    pub async fn list(&self) -> io::Result<Vec<String>> {
+        Ok(queue::entries(&self.dir)
+            .await?
+            .iter()
+            .filter_map(|p| p.file_stem()?.to_str().map(str::to_owned))
+            .collect())
+    }
+
+    pub async fn get(&self, id: &str) -> io::Result<Option<DeadLetter>> {
+        let path = match self.path(id) {
+            Some(p) => p,
+            None => return Ok(None),
+        };
+        match fs::read(&path).await {
+            Ok(c) => Ok(Some(serde_json::from_slice(&c)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Removes a dead letter, returning whether it existed
+    pub async fn remove(&self, id: &str) -> io::Result<bool> {
+        let path = match self.path(id) {
+            Some(p) => p,
+            None => return Ok(false),
+        };
+        match fs::remove_file(&path).await {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn path(&self, id: &str) -> Option<PathBuf> {
+        // Ids are generated by `queue::unique_name`, anything else could escape the directory
+        if !id.is_empty() && id.chars().all(|c| c.is_ascii_digit() || c == '-') {
+            Some(self.dir.join(format!("{}.json", id)))
+        } else {
+            None
+        }
+    }
+}
+
+/// Handles the `/_admin/dead-letters` endpoints
+pub async fn admin(
+    method: Method,
+    tail: Tail,
+    authorization: Option<String>,
+    token: Option<Arc<String>>,
+    queue: Option<Arc<Queue>>,
+) -> Result<Response, Rejection> {
+    let (token, queue) = match (token, queue) {
+        (Some(t), Some(q)) => (t, q),
+        _ => return Err(warp::reject::not_found()),
+    };
+    let authorized = authorization
+        .as_deref()
+        .and_then(|a| a.strip_prefix("Bearer "))
+        .is_some_and(|a| bool::from(a.as_bytes().ct_eq(token.as_bytes())));
+    if !authorized {
+        return Ok(
+            warp::reply::with_status("Unauthorized", StatusCode::UNAUTHORIZED).into_response(),
+        );
+    }
+
+    let dead_letters = &queue.dead_letters;
+    let segments: Vec<&str> = tail.as_str().split('/').filter(|s| !s.is_empty()).collect();
+    let result = match (method, segments.as_slice()) {
+        (Method::GET, []) => list(dead_letters).await.map(|l| warp::reply::json(&l)),
+        (Method::DELETE, []) => purge(dead_letters)
+            .await
+            .map(|n| warp::reply::json(&serde_json::json!({ "removed": n }))),
+        (Method::GET, [id]) => match dead_letters.get(id).await {
+            Ok(Some(l)) => Ok(warp::reply::json(&l)),
+            Ok(None) => return Err(warp::reject::not_found()),
+            Err(e) => Err(e),
+        },
+        (Method::DELETE, [id]) => match dead_letters.remove(id).await {
+            Ok(true) => Ok(warp::reply::json(&serde_json::json!({}))),
+            Ok(false) => return Err(warp::reject::not_found()),
+            Err(e) => Err(e),
+        },
+        (Method::POST, [id, "replay"]) => match dead_letters.get(id).await {
+            Ok(Some(l)) => {
+                async {
+                    queue.push(&l.into_job()).await?;
+                    dead_letters.remove(id).await?;
+                    Ok(warp::reply::json(&serde_json::json!({})))
+                }
+                .await
+            }
+            Ok(None) => return Err(warp::reject::not_found()),
+            Err(e) => Err(e),
+        },
+        _ => return Err(warp::reject::not_found()),
+    };
+
+    match result {
+        Ok(r) => Ok(r.into_response()),
+        Err(e) => {
+            eprintln!("Can't access dead letters: {}", e);
+            Ok(
+                warp::reply::with_status(
+                    "Internal Server Error",
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                )
+                .into_response(),
+            )
+        }
+    }
+}
+
+/// Summary of a dead letter, leaving out the bodies
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Summary {
+    id: String,
+    webhook: String,
+    url: String,
+    error: String,
+    attempts: u32,
+    failed_at: u64,
+}
+
+async fn list(dead_letters: &DeadLetters) -> io::Result<Vec<Summary>> {
+    let mut summaries = Vec::new();
+    for id in dead_letters.list().await? {
+        if let Some(l) = dead_letters.get(&id).await? {
+            summaries.push(Summary {
+                id,
+                webhook: l.webhook,
+                url: l.url,
+                error: l.error,
+                attempts: l.attempts,
+                failed_at: l.failed_at,
+            });
+        }
+    }
+    Ok(summaries)
+}
+
+async fn purge(dead_letters: &DeadLetters) -> io::Result<usize> {
+    let mut removed = 0;
+    for id in dead_letters.list().await? {
+        if dead_letters.remove(&id).await? {
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Runs the `dead-letters` subcommand
+pub async fn command(args: &[String]) {
+    const USAGE: &str =
+        "Usage: forwardhook dead-letters <list | show ID | replay ID | purge ID|all> [CONFIG_FILE]";
+
+    let (action, id, config_path) = match args {
+        [action, rest @ ..] if action == "list" => (action.as_str(), None, rest.first()),
+        [action, id, rest @ ..] if ["show", "replay", "purge"].contains(&action.as_str()) => {
+            (action.as_str(), Some(id.as_str()), rest.first())
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(64)
+        }
+    };
+    let config = crate::load_config(config_path.map_or("forwardhook.json", |p| p.as_str())).await;
+    let data_dir = config
+        .data_dir
+        .as_ref()
+        .unwrap_or_exit("Invalid config file: `dataDir` is missing", 66);
+    let dead_letters = DeadLetters::open(data_dir)
+        .await
+        .unwrap_or_exit("Can't open dead letters", 74);
+
+    let result = match (action, id) {
+        ("list", _) => list(&dead_letters).await.map(|summaries| {
+            for s in summaries {
+                println!(
+                    "{}\t{}\t{}\t{}\t{}",
+                    s.id, s.webhook, s.url, s.attempts, s.error
+                );
+            }
+        }),
+        ("purge", Some("all")) => purge(&dead_letters)
+            .await
+            .map(|n| println!("Removed {} dead letters", n)),
+        (action, Some(id)) => match dead_letters.get(id).await {
+            Ok(Some(letter)) => match action {
+                "show" => {
+                    println!("{}", serde_json::to_string_pretty(&letter).unwrap());
+                    Ok(())
+                }
+                "purge" => dead_letters.remove(id).await.map(drop),
+                _ => replay(&config, &dead_letters, id, letter).await,
+            },
+            Ok(None) => {
+                eprintln!("No dead letter `{}`", id);
+                process::exit(66)
+            }
+            Err(e) => Err(e),
+        },
+        _ => unreachable!(),
+    };
+    result.unwrap_or_exit("Can't access dead letters", 74);
+}
+
+/// Delivers a dead letter right away, removing it on success
+async fn replay(
+    config: &Config,
+    dead_letters: &DeadLetters,
+    id: &str,
+    letter: DeadLetter,
+) -> io::Result<()> {
+    let client = crate::client(config);
+    let (webhook, target) = config.target(&letter.webhook, &letter.url).unwrap_or_exit(
+        &format!("`{}` has no target `{}`", letter.webhook, letter.url),
+        66,
+    );
+
+    match forward::send(&client, &letter.webhook, webhook, target, &letter.payload).await {
+        Ok(()) => {
+            dead_letters.remove(id).await?;
+            println!("Delivered `{}` to `{}`", id, target.url);
+            Ok(())
+        }
+        Err(f) => {
+            eprintln!(
+                "Can't forward `{}` to `{}`: {}",
+                letter.webhook, target.url, f
+            );
+            process::exit(69)
+        }
+    }
+}
@@ -0,0 +1,295 @@
+use crate::{xml, Format, JsonObject, Method, Target, Webhook};
+use futures::future;
+use rand::Rng;
+use reqwest::{
+    header::{CONTENT_TYPE, RETRY_AFTER},
+    Client, Response, StatusCode,
+};
+use serde::Deserialize;
+use std::{
+    convert::TryFrom,
+    fmt,
+    ops::Range,
+    time::{Duration, SystemTime},
+};
+use tokio::time;
+
+/// Maximum number of characters of a failed response body to log
+const LOGGED_BODY_LEN: usize = 256;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Retry {
+    #[serde(default = "Retry::default_max_attempts")]
+    max_attempts: u32,
+    /// Delay before the first retry in milliseconds
+    #[serde(default = "Retry::default_initial_delay")]
+    initial_delay: u64,
+    #[serde(default = "Retry::default_multiplier")]
+    multiplier: f64,
+    /// Upper bound for any delay in milliseconds, including `Retry-After`
+    #[serde(default = "Retry::default_max_delay")]
+    max_delay: u64,
+    /// Fraction of each delay which is randomized
+    #[serde(default)]
+    jitter: f64,
+    #[serde(default = "Retry::default_retry_on")]
+    retry_on: Vec<StatusRange>,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self {
+            max_attempts: Self::default_max_attempts(),
+            initial_delay: Self::default_initial_delay(),
+            multiplier: Self::default_multiplier(),
+            max_delay: Self::default_max_delay(),
+            jitter: 0.0,
+            retry_on: Self::default_retry_on(),
+        }
+    }
+}
+
+impl Retry {
+    fn default_max_attempts() -> u32 {
+        5
+    }
+    fn default_initial_delay() -> u64 {
+        1000
+    }
+    fn default_multiplier() -> f64 {
+        2.0
+    }
+    fn default_max_delay() -> u64 {
+        60_000
+    }
+    fn default_retry_on() -> Vec<StatusRange> {
+        vec![
+            StatusRange {
+                start: 429,
+                end: 429,
+            },
+            StatusRange {
+                start: 500,
+                end: 599,
+            },
+        ]
+    }
+
+    /// Delay before the given retry, starting at 1
+    fn delay(&self, retry: u32, retry_after: Option<Duration>) -> Duration {
+        let backoff = self.initial_delay as f64 * self.multiplier.powi(retry as i32 - 1);
+        let jitter = if self.jitter > 0.0 {
+            rand::thread_rng().gen_range(0.0..=self.jitter.min(1.0))
+        } else {
+            0.0
+        };
+        let backoff = Duration::from_millis((backoff * (1.0 - jitter)) as u64);
+
+        let delay = match retry_after {
+            Some(retry_after) => backoff.max(retry_after),
+            None => backoff,
+        };
+        delay.min(Duration::from_millis(self.max_delay))
+    }
+
+    fn retries(&self, error: &Error) -> bool {
+        match error {
+            Error::Request(_) => true,
+            Error::Status(status, ..) => self.retry_on.iter().any(|r| r.contains(*status)),
+        }
+    }
+}
+
+#[derive(Deserialize, Copy, Clone)]
+#[serde(try_from = "StatusRangeRepr")]
+pub struct StatusRange {
+    start: u16,
+    end: u16,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StatusRangeRepr {
+    Code(u16),
+    Range(String),
+}
+
+impl TryFrom<StatusRangeRepr> for StatusRange {
+    type Error = String;
+
+    fn try_from(repr: StatusRangeRepr) -> Result<Self, Self::Error> {
+        let range = match repr {
+            StatusRangeRepr::Code(code) => Some((code, code)),
+            StatusRangeRepr::Range(range) => match range.split_once('-') {
+                Some((start, end)) => start.trim().parse().ok().zip(end.trim().parse().ok()),
+                None => range
+                    .strip_suffix("xx")
+                    .and_then(|class| class.parse::<u16>().ok())
+                    .filter(|class| (1..=5).contains(class))
+                    .map(|class| (class * 100, class * 100 + 99)),
+            },
+        };
+        match range {
+            Some((start, end)) if start <= end => Ok(StatusRange { start, end }),
+            _ => Err("expected a status code, a range like `200-299` or a class like `2xx`".into()),
+        }
+    }
+}
+
+impl StatusRange {
+    fn contains(self, status: StatusCode) -> bool {
+        (self.start..=self.end).contains(&status.as_u16())
+    }
+}
+
+pub enum Error {
+    Request(reqwest::Error),
+    Status(StatusCode, String, Option<Duration>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Request(e) => e.fmt(f),
+            Error::Status(status, body, _) => write!(f, "{}: {}", status, body),
+        }
+    }
+}
+
+/// An error which persisted after every attempt
+pub struct Failure {
+    pub error: Error,
+    pub attempts: u32,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.attempts {
+            1 => self.error.fmt(f),
+            n => write!(f, "{} (after {} attempts)", self.error, n),
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Request(e)
+    }
+}
+
+/// Forwards every payload to its matching target among `targets` concurrently, logging failures
+pub async fn dispatch(
+    client: &Client,
+    id: &str,
+    webhook: &Webhook,
+    targets: Range<usize>,
+    payloads: &[JsonObject],
+) -> Vec<Result<(), Failure>> {
+    let sends = webhook.targets[targets]
+        .iter()
+        .zip(payloads)
+        .map(|(target, payload)| async move {
+            let result = send(client, id, webhook, target, payload).await;
+            if let Err(e) = &result {
+                eprintln!("Can't forward `{}` to `{}`: {}", id, target.url, e);
+            }
+            result
+        });
+    future::join_all(sends).await
+}
+
+/// Forwards a payload to a target, retrying according to the webhook policy
+pub async fn send(
+    client: &Client,
+    id: &str,
+    webhook: &Webhook,
+    target: &Target,
+    payload: &JsonObject,
+) -> Result<(), Failure> {
+    let mut attempts = 1;
+    loop {
+        let error = match attempt(client, webhook, target, payload).await {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        let retry = match &webhook.retry {
+            Some(r) if attempts < r.max_attempts && r.retries(&error) => r,
+            _ => return Err(Failure { error, attempts }),
+        };
+
+        let retry_after = match &error {
+            Error::Status(_, _, retry_after) => *retry_after,
+            Error::Request(_) => None,
+        };
+        let delay = retry.delay(attempts, retry_after);
+        eprintln!(
+            "Can't forward `{}` to `{}`, retrying in {}ms: {}",
+            id,
+            target.url,
+            delay.as_millis(),
+            error
+        );
+        time::delay_for(delay).await;
+        attempts += 1;
+    }
+}
+
+async fn attempt(
+    client: &Client,
+    webhook: &Webhook,
+    target: &Target,
+    payload: &JsonObject,
+) -> Result<(), Error> {
+    let mut request = match target.method {
+        Method::Post => client.post(&target.url),
+        Method::Put => client.put(&target.url),
+        Method::Patch => client.patch(&target.url),
+    };
+    for (name, value) in &target.headers {
+        request = request.header(name.as_str(), value.as_str());
+    }
+    request = match target.format {
+        Format::Json => request.json(payload),
+        Format::Xml => {
+            if !target
+                .headers
+                .keys()
+                .any(|n| n.eq_ignore_ascii_case("content-type"))
+            {
+                request = request.header(CONTENT_TYPE, "application/xml");
+            }
+            request.body(xml::render(payload, &target.xml_root))
+        }
+    };
+    let response = request.send().await?;
+
+    let status = response.status();
+    let accepted = match &webhook.accepted_status {
+        Some(ranges) => ranges.iter().any(|r| r.contains(status)),
+        None => status.is_success(),
+    };
+    if accepted {
+        Ok(())
+    } else {
+        let retry_after = retry_after(&response);
+        let body = response.text().await.unwrap_or_default();
+        Err(Error::Status(
+            status,
+            body.chars().take(LOGGED_BODY_LEN).collect(),
+            retry_after,
+        ))
+    }
+}
+
+/// Parses a `Retry-After` header, either in seconds or as an HTTP date
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    match value.parse() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => httpdate::parse_http_date(value)
+            .ok()?
+            .duration_since(SystemTime::now())
+            .ok(),
+    }
+}
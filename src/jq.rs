@@ -0,0 +1,400 @@
+use crate::{request::Request, JsonObject};
+use jaq_core::{
+    load::{
+        self,
+        lex::StrPart,
+        parse::{Pattern, Term},
+        Arena, File, Loader,
+    },
+    path::{Part, Path},
+    Compiler, Ctx, Error, Exn, Native, RcIter, ValX,
+};
+use jaq_json::Val;
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use std::{
+    cell::Cell,
+    cmp::Ordering,
+    convert::TryFrom,
+    iter,
+    time::{Duration, Instant},
+};
+
+thread_local! {
+    /// When the filter running on this thread has to stop
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Variables holding the parts of the request other than the body, which is the input
+const VARIABLES: [&str; 4] = ["$headers", "$query", "$path", "$meta"];
+
+/// Standard definitions which can loop forever, redefined to check the deadline on every iteration
+const GUARDED: &str = r#"
+def repeat(f): def rec: f, (_deadline | rec); rec;
+def recurse(f): def rec: ., (f | _deadline | rec); rec;
+def recurse: recurse(.[]?);
+def recurse(f; cond): recurse(f | select(cond));
+def while(cond; update): def rec: if cond then ., (update | _deadline | rec) else empty end; rec;
+def until(cond; update): def rec: if cond then . else update | _deadline | rec end; rec;
+"#;
+
+/// A jq filter, compiled when the config is loaded
+#[derive(Deserialize)]
+#[serde(try_from = "JqConfig")]
+pub struct Jq {
+    filter: jaq_core::Filter<Native<Val>>,
+    timeout: Duration,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JqConfig {
+    Filter(String),
+    Options {
+        filter: String,
+        /// Milliseconds
+        #[serde(default = "default_timeout")]
+        timeout: u64,
+    },
+}
+
+fn default_timeout() -> u64 {
+    100
+}
+
+fn expired() -> bool {
+    DEADLINE.with(|d| d.get().is_some_and(|d| Instant::now() > d))
+}
+
+fn timed_out<'a>() -> Exn<'a, Val> {
+    Exn::from(Error::str("timeout"))
+}
+
+/// `range/3` from the standard library, stopping with an error once the deadline is past
+fn range<'a>(from: Val, to: Val, by: Val) -> impl Iterator<Item = ValX<'a, Val>> {
+    let direction = by.partial_cmp(&Val::from(0)).unwrap_or(Ordering::Equal);
+    let mut next = Some(Ok(from));
+    iter::from_fn(move || {
+        let x = match next.take()? {
+            Ok(x) => x,
+            Err(e) => return Some(Err(e)),
+        };
+        let more = match direction {
+            Ordering::Greater => x < to,
+            Ordering::Less => x > to,
+            Ordering::Equal => x != to,
+        };
+        if !more {
+            return None;
+        }
+        if expired() {
+            return Some(Err(timed_out()));
+        }
+        next = Some((x.clone() + by.clone()).map_err(Exn::from));
+        Some(Ok(x))
+    })
+}
+
+impl TryFrom<JqConfig> for Jq {
+    type Error = String;
+
+    fn try_from(config: JqConfig) -> Result<Self, Self::Error> {
+        let (code, timeout) = match config {
+            JqConfig::Filter(filter) => (filter, default_timeout()),
+            JqConfig::Options { filter, timeout } => (filter, timeout),
+        };
+        let guarded = load::parse(GUARDED, |p| p.defs()).unwrap();
+        let loader = Loader::new(jaq_std::defs().chain(jaq_json::defs()).chain(guarded));
+        let arena = Arena::default();
+        let modules = loader
+            .load(
+                &arena,
+                File {
+                    code: &code,
+                    path: (),
+                },
+            )
+            .map_err(|errors| {
+                let (_, error) = errors.into_iter().next().unwrap();
+                match error {
+                    load::Error::Io(e) => e.into_iter().map(|(_, e)| e).collect(),
+                    load::Error::Lex(e) => e.first().map_or_else(String::new, |(expected, at)| {
+                        unexpected(expected.as_str(), at)
+                    }),
+                    load::Error::Parse(e) => {
+                        e.first().map_or_else(String::new, |(expected, at)| {
+                            unexpected(expected.as_str(), at)
+                        })
+                    }
+                }
+            })?;
+        // Definitions calling themselves could run for any time without reaching a deadline check
+        if let Some(name) = load::parse(&code, |p| p.term())
+            .and_then(|term| recursive(&term, &mut Vec::new()).map(str::to_owned))
+        {
+            return Err(format!("recursive definition of `{}`", name));
+        }
+
+        let funs = jaq_std::funs()
+            .filter(|(name, arguments, _)| (*name, arguments.len()) != ("range", 3))
+            .chain(jaq_json::funs())
+            .chain([
+                (
+                    "_deadline",
+                    jaq_std::v(0),
+                    Native::new(|_, cv| {
+                        let output = if expired() {
+                            Err(timed_out())
+                        } else {
+                            Ok(cv.1)
+                        };
+                        Box::new(iter::once(output))
+                    }),
+                ),
+                (
+                    "range",
+                    jaq_std::v(3),
+                    Native::new(|_, mut cv| {
+                        let by = cv.0.pop_var();
+                        let to = cv.0.pop_var();
+                        let from = cv.0.pop_var();
+                        Box::new(range(from, to, by))
+                    }),
+                ),
+            ]);
+        let filter = Compiler::default()
+            .with_funs(funs)
+            .with_global_vars(VARIABLES.iter().copied())
+            .compile(modules)
+            .map_err(|errors| {
+                let (_, undefined) = errors.into_iter().next().unwrap();
+                undefined
+                    .iter()
+                    .map(|(name, u)| format!("undefined {} `{}`", u.as_str(), name))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            })?;
+        Ok(Jq {
+            filter,
+            timeout: Duration::from_millis(timeout),
+        })
+    }
+}
+
+/// Finds a definition calling itself in the term, given the filters in scope
+/// and whether they are still being defined
+fn recursive<'s>(term: &Term<&'s str>, scope: &mut Vec<(&'s str, usize, bool)>) -> Option<&'s str> {
+    match term {
+        Term::Id | Term::Recurse | Term::Num(_) | Term::Break(_) | Term::Var(_) => None,
+        Term::Str(_, parts) => all(
+            parts.iter().filter_map(|p| match p {
+                StrPart::Term(t) => Some(t),
+                _ => None,
+            }),
+            scope,
+        ),
+        Term::Arr(t) => all(t.iter().map(|t| &**t), scope),
+        Term::Obj(entries) => all(
+            entries
+                .iter()
+                .flat_map(|(k, v)| iter::once(k).chain(v.as_ref())),
+            scope,
+        ),
+        Term::Neg(t) | Term::Label(_, t) => recursive(t, scope),
+        Term::Pipe(l, pattern, r) => recursive(l, scope)
+            .or_else(|| pattern.as_ref().and_then(|p| recursive_pattern(p, scope)))
+            .or_else(|| recursive(r, scope)),
+        Term::BinOp(l, _, r) => recursive(l, scope).or_else(|| recursive(r, scope)),
+        Term::Fold(_, xs, pattern, args) => recursive(xs, scope)
+            .or_else(|| recursive_pattern(pattern, scope))
+            .or_else(|| all(args.iter(), scope)),
+        Term::TryCatch(t, catch) => {
+            recursive(t, scope).or_else(|| all(catch.iter().map(|t| &**t), scope))
+        }
+        Term::IfThenElse(branches, otherwise) => all(
+            branches
+                .iter()
+                .flat_map(|(cond, then)| [cond, then])
+                .chain(otherwise.iter().map(|t| &**t)),
+            scope,
+        ),
+        Term::Def(defs, rest) => {
+            let outer = scope.len();
+            let found = defs.iter().find_map(|def| {
+                scope.push((def.name, def.args.len(), true));
+                let defined = scope.len();
+                scope.extend(
+                    def.args
+                        .iter()
+                        .map(|arg| (arg.trim_start_matches('$'), 0, false)),
+                );
+                let found = recursive(&def.body, scope);
+                scope.truncate(defined);
+                scope[defined - 1].2 = false;
+                found
+            });
+            let found = found.or_else(|| recursive(rest, scope));
+            scope.truncate(outer);
+            found
+        }
+        Term::Call(name, args) => {
+            let called = scope
+                .iter()
+                .rev()
+                .find(|(n, arity, _)| n == name && *arity == args.len());
+            match called {
+                Some((_, _, true)) => Some(*name),
+                _ => all(args.iter(), scope),
+            }
+        }
+        Term::Path(t, Path(parts)) => recursive(t, scope).or_else(|| {
+            all(
+                parts.iter().flat_map(|(part, _)| match part {
+                    Part::Index(i) => vec![i],
+                    Part::Range(from, to) => from.iter().chain(to).collect(),
+                }),
+                scope,
+            )
+        }),
+    }
+}
+
+fn all<'a, 's: 'a>(
+    terms: impl IntoIterator<Item = &'a Term<&'s str>>,
+    scope: &mut Vec<(&'s str, usize, bool)>,
+) -> Option<&'s str> {
+    terms.into_iter().find_map(|t| recursive(t, scope))
+}
+
+fn recursive_pattern<'s>(
+    pattern: &Pattern<&'s str>,
+    scope: &mut Vec<(&'s str, usize, bool)>,
+) -> Option<&'s str> {
+    match pattern {
+        Pattern::Var(_) => None,
+        Pattern::Arr(patterns) => patterns.iter().find_map(|p| recursive_pattern(p, scope)),
+        Pattern::Obj(entries) => entries
+            .iter()
+            .find_map(|(k, v)| recursive(k, scope).or_else(|| recursive_pattern(v, scope))),
+    }
+}
+
+fn unexpected(expected: &str, at: &str) -> String {
+    match at.chars().take(20).collect::<String>() {
+        at if at.is_empty() => format!("expected {} at the end", expected),
+        at => format!("expected {} at `{}`", expected, at),
+    }
+}
+
+impl Jq {
+    /// Runs the filter on the request body, returning its first output which has to be an object
+    pub fn run(&self, request: &Request) -> Result<JsonObject, String> {
+        let variables = vec![
+            request.headers.clone().into(),
+            request.query.clone().into(),
+            request.path.clone().into(),
+            request.meta.clone().into(),
+        ];
+        let inputs = RcIter::new(core::iter::empty());
+        // Parts of the filter can already be evaluated while its outputs are being set up
+        DEADLINE.with(|d| d.set(Some(Instant::now() + self.timeout)));
+        let output = self
+            .filter
+            .run((Ctx::new(variables, &inputs), request.body.clone().into()))
+            .next();
+        DEADLINE.with(|d| d.set(None));
+
+        match output {
+            Some(Ok(value)) => match JsonValue::from(value) {
+                JsonValue::Object(o) => Ok(o),
+                v => Err(format!("expected an object, got `{}`", v)),
+            },
+            Some(Err(e)) => Err(e.to_string()),
+            None => Err("no output".to_owned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn run(filter: &str) -> Result<JsonObject, String> {
+        let jq: Jq = serde_json::from_value(json!({ "filter": filter, "timeout": 50 })).unwrap();
+        let request = Request {
+            body: json!({}),
+            headers: json!({}),
+            query: json!({}),
+            path: json!([]),
+            meta: json!({}),
+        };
+        jq.run(&request)
+    }
+
+    fn times_out(filter: &str) -> bool {
+        let start = Instant::now();
+        let result = run(filter);
+        start.elapsed() < Duration::from_secs(5) && result.is_err_and(|e| e.contains("timeout"))
+    }
+
+    #[test]
+    fn runs() {
+        assert_eq!(
+            run("{a: [range(0; 10; 3)], b: [range(5; 0; -2)], c: [range(3)], d: [limit(2; repeat(1))], e: (0 | until(. > 5; . + 2)), f: [1 | while(. < 9; . * 3)], g: [limit(3; 0 | recurse(. + 1))]}"),
+            Ok(json!({
+                "a": [0, 3, 6, 9],
+                "b": [5, 3, 1],
+                "c": [0, 1, 2],
+                "d": [1, 1],
+                "e": 6,
+                "f": [1, 3],
+                "g": [0, 1, 2],
+            })
+            .as_object()
+            .unwrap()
+            .clone())
+        );
+        assert_eq!(run("[1]"), Err("expected an object, got `[1]`".to_owned()));
+    }
+
+    #[test]
+    fn loops_time_out() {
+        assert!(times_out("{a: [range(1e12)] | length}"));
+        assert!(times_out("{a: [range(0; 1; 0)] | length}"));
+        assert!(times_out("{a: [repeat(1)] | length}"));
+        assert!(times_out("{a: (0 | until(false; .))}"));
+        assert!(times_out("{a: [0 | while(true; .)] | length}"));
+        assert!(times_out("{a: [0 | recurse(. + 1)] | length}"));
+        assert!(times_out("{a: [[range(1e6)] | recurse] | length}"));
+        assert!(times_out("{a: [0 | recurse(. + 1; true)] | length}"));
+    }
+
+    #[test]
+    fn recursion() {
+        let load = |filter: &str| {
+            serde_json::from_value::<Jq>(json!(filter))
+                .err()
+                .map(|e| e.to_string())
+        };
+        assert_eq!(
+            load("def f: 1, f; {a: [limit(1e8; f)]}"),
+            Some("recursive definition of `f`".to_owned())
+        );
+        assert_eq!(
+            load("def f: (f | . + 1); {a: f}"),
+            Some("recursive definition of `f`".to_owned())
+        );
+        assert_eq!(
+            load("def f: def g: [f]; g; {a: f}"),
+            Some("recursive definition of `f`".to_owned())
+        );
+        assert_eq!(load("def f(g): {a: \"\\(g)\"}; f(f(1))"), None);
+        assert_eq!(load("def f: def f: 2; f + 1; {a: f}"), None);
+        assert_eq!(load("def f(f): f; {a: f(1)}"), None);
+        assert_eq!(
+            run("def f: def f: 2; f + 1; {a: f}"),
+            Ok(json!({"a": 3}).as_object().unwrap().clone())
+        );
+    }
+}
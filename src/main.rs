@@ -1,35 +1,65 @@
+mod body;
+mod condition;
+mod dead_letter;
+mod forward;
+mod jq;
+mod path;
+mod plugin;
+mod queue;
+mod request;
+mod script;
+mod secret;
+mod template;
+mod transform;
+mod verify;
+mod xml;
+
+use crate::{
+    condition::Condition,
+    dead_letter::DeadLetter,
+    forward::{Retry, StatusRange},
+    jq::Jq,
+    plugin::Plugin,
+    queue::{Job, Queue},
+    request::{Request, Source},
+    script::Script,
+    template::Template,
+    transform::Transform,
+    verify::Verify,
+};
 use log::debug;
-use reqwest::Client;
-use serde::Deserialize;
-use serde_json::{Value as JsonValue, Value};
-use std::{collections::HashMap, convert::Infallible, env, fmt, process, sync::Arc};
-use tokio::fs;
-use warp::{http::StatusCode, Filter, Rejection, Reply};
+use reqwest::{
+    header::{HeaderName, HeaderValue},
+    Client,
+};
+use serde::{Deserialize, Deserializer};
+use serde_json::{json, Value as JsonValue, Value};
+use std::{
+    borrow::Cow, collections::HashMap, convert::Infallible, env, fmt, mem, ops::Range,
+    path::PathBuf, process, sync::Arc,
+};
+use tokio::{fs, task};
+use warp::{
+    http::{HeaderMap, StatusCode},
+    hyper::body::Bytes,
+    reply::Response,
+    Filter, Rejection, Reply,
+};
 
 type JsonObject = serde_json::Map<String, JsonValue>;
 type JsonArray = Vec<JsonValue>;
 
 trait TryExt<T> {
     fn unwrap_or_exit(self, message: &str, code: i32) -> T;
-    fn or_reject(self) -> Result<T, Rejection>;
     fn or_log_and_reject(self, message: &str) -> Result<T, Rejection>;
 }
-impl<T, E: std::error::Error> TryExt<T> for Result<T, E> {
+impl<T, E: fmt::Display> TryExt<T> for Result<T, E> {
     fn unwrap_or_exit(self, message: &str, code: i32) -> T {
         self.unwrap_or_else(|e| {
             eprintln!("{}: {}", message, e);
             process::exit(code)
         })
     }
-    fn or_reject(self) -> Result<T, Rejection> {
-        match self {
-            Ok(v) => Ok(v),
-            Err(e) => {
-                eprintln!("{}", e);
-                Err(warp::reject())
-            }
-        }
-    }
     fn or_log_and_reject(self, message: &str) -> Result<T, Rejection> {
         match self {
             Ok(v) => Ok(v),
@@ -47,12 +77,6 @@ impl<T> TryExt<T> for Option<T> {
             process::exit(code)
         })
     }
-    fn or_reject(self) -> Result<T, Rejection> {
-        match self {
-            Some(v) => Ok(v),
-            None => Err(warp::reject()),
-        }
-    }
     fn or_log_and_reject(self, message: &str) -> Result<T, Rejection> {
         match self {
             Some(v) => Ok(v),
@@ -92,7 +116,7 @@ impl JsonExt<&str> for JsonObject {
 }
 impl JsonExt<usize> for JsonArray {
     fn get_or_insert_mut(&mut self, index: usize, insert: OrInsertJsonValue) -> &mut Value {
-        if self.len() < index {
+        if self.len() <= index {
             for _ in self.len()..index {
                 self.push(JsonValue::Null);
             }
@@ -103,7 +127,7 @@ impl JsonExt<usize> for JsonArray {
 }
 
 #[derive(Debug)]
-struct StrError(&'static str);
+pub struct StrError(&'static str);
 impl fmt::Display for StrError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.0.fmt(f)
@@ -111,10 +135,10 @@ impl fmt::Display for StrError {
 }
 impl std::error::Error for StrError {}
 
-fn inject<T: Send + Sync>(
-    arc: Arc<T>,
-) -> impl Filter<Extract = (Arc<T>,), Error = Infallible> + Clone {
-    warp::any().map(move || arc.clone())
+fn inject<T: Clone + Send + Sync>(
+    value: T,
+) -> impl Filter<Extract = (T,), Error = Infallible> + Clone {
+    warp::any().map(move || value.clone())
 }
 
 #[derive(Deserialize)]
@@ -123,22 +147,125 @@ struct Config {
     port: u16,
     user_agent: Option<String>,
     webhooks: HashMap<String, Webhook>,
+    data_dir: Option<PathBuf>,
+    admin_token: Option<String>,
     #[serde(default)]
     debug: bool,
 }
 
+impl Config {
+    /// Finds a target of a webhook by URL, taking the first one when several share it
+    fn target(&self, webhook: &str, url: &str) -> Option<(&Webhook, &Target)> {
+        let webhook = self.webhooks.get(webhook)?;
+        let target = webhook.targets.iter().find(|t| t.url == url)?;
+        Some((webhook, target))
+    }
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct Webhook {
-    forward_url: String,
+    forward_url: Option<String>,
     #[serde(default)]
     forward_method: Method,
+    #[serde(default)]
+    forward_format: Format,
+    #[serde(default = "default_xml_root")]
+    forward_xml_root: String,
+    #[serde(default)]
     fields: Vec<Field>,
+    #[serde(default)]
+    targets: Vec<Target>,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    policy: Policy,
+    #[serde(default)]
+    queued: bool,
+    accepted_status: Option<Vec<StatusRange>>,
+    retry: Option<Retry>,
     reply: Option<JsonObject>,
+    verify: Option<Verify>,
+    /// jq filter producing the forwarded JSON, instead of the fields of the targets
+    transform: Option<Jq>,
+    /// Rhai script producing the forwarded JSON, instead of the fields of the targets
+    script: Option<Script>,
+    /// WebAssembly plugin producing the forwarded JSON, instead of the fields of the targets
+    plugin: Option<Plugin>,
+    /// Condition for the request to be forwarded, others are acknowledged but dropped
+    when: Option<Condition>,
+    /// Alternative targets, the first route matching the request is taken
+    #[serde(default)]
+    routes: Vec<Route>,
+    /// Object the fields are written on top of
+    #[serde(default)]
+    base: Base,
+    /// Paths removed from the base
+    #[serde(default, deserialize_with = "paths")]
+    remove: Vec<JsonPath>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Route {
+    /// Condition for the route to be taken, only the last route can omit it to catch the rest
+    when: Option<Condition>,
+    forward_url: Option<String>,
+    #[serde(default)]
+    forward_method: Method,
+    #[serde(default)]
+    forward_format: Format,
+    #[serde(default = "default_xml_root")]
+    forward_xml_root: String,
+    #[serde(default)]
+    fields: Vec<Field>,
+    #[serde(default)]
+    targets: Vec<Target>,
+    /// Indices of the targets of the route among those of the webhook
+    #[serde(skip)]
+    range: Range<usize>,
 }
 
-#[derive(Deserialize, Copy, Clone)]
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Target {
+    url: String,
+    #[serde(default)]
+    method: Method,
+    #[serde(default)]
+    format: Format,
+    /// Name of the root element when the format is XML
+    #[serde(default = "default_xml_root")]
+    xml_root: String,
+    #[serde(default)]
+    fields: Vec<Field>,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Copy, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+enum Policy {
+    #[default]
+    All,
+    Any,
+    FireAndForget,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+enum Base {
+    #[default]
+    Empty,
+    /// The incoming body
+    Passthrough,
+    /// The object at a path of the incoming body
+    From(#[serde(deserialize_with = "path::deserialize")] JsonPath),
+}
+
+#[derive(Deserialize, Copy, Clone, Default, PartialEq)]
 enum Method {
+    #[default]
     #[serde(rename = "POST")]
     Post,
     #[serde(rename = "PUT")]
@@ -146,28 +273,96 @@ enum Method {
     #[serde(rename = "PATCH")]
     Patch,
 }
-impl Default for Method {
-    fn default() -> Self {
-        Self::Post
-    }
+
+/// Format of the forwarded body
+#[derive(Deserialize, Copy, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+enum Format {
+    #[default]
+    Json,
+    Xml,
+}
+
+fn default_xml_root() -> String {
+    "root".to_owned()
 }
 
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct Field {
-    from: JsonPath,
+    #[serde(default)]
+    source: Source,
+    #[serde(default, deserialize_with = "path::deserialize_optional")]
+    from: Option<JsonPath>,
+    #[serde(default, deserialize_with = "some")]
+    value: Option<JsonValue>,
+    template: Option<Template>,
+    #[serde(default)]
+    transforms: Vec<Transform>,
+    #[serde(deserialize_with = "path::deserialize")]
     to: JsonPath,
     #[serde(default)]
     optional: bool,
+    /// Written instead when the value can't be resolved
+    #[serde(default, deserialize_with = "some")]
+    default: Option<JsonValue>,
+    /// Whether a `null` value counts as missing
+    #[serde(default)]
+    null_as_missing: bool,
+    /// Condition for the field to be included
+    when: Option<Condition>,
+}
+
+fn paths<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<JsonPath>, D::Error> {
+    #[derive(Deserialize)]
+    struct Path(#[serde(deserialize_with = "path::deserialize")] JsonPath);
+
+    Vec::<Path>::deserialize(deserializer).map(|paths| paths.into_iter().map(|p| p.0).collect())
+}
+
+/// Deserializes a present value as `Some`, so that `null` can be told apart from a missing value
+fn some<'de, D: Deserializer<'de>, T: Deserialize<'de>>(
+    deserializer: D,
+) -> Result<Option<T>, D::Error> {
+    T::deserialize(deserializer).map(Some)
 }
 
 type JsonPath = Vec<JsonPathSegment>;
 
+#[derive(Deserialize, Clone)]
+#[serde(from = "RawJsonPathSegment")]
+enum JsonPathSegment {
+    Key(String),
+    /// Negative indices count from the end of the array, `-1` being the last element
+    Index(isize),
+    /// `"*"`, every element of an array or value of an object
+    Wildcard,
+    /// `".."`, the value itself and every value nested in it, only valid when reading
+    Descendants,
+    /// `"-"` or `"[]"`, a new element pushed at the end of an array, only valid when writing
+    Append,
+    /// `[?(...)]` in a JSONPath, every element of an array or value of an object matching a
+    /// condition, only valid when reading
+    Filter(path::Filter),
+}
+
 #[derive(Deserialize)]
 #[serde(untagged)]
-enum JsonPathSegment {
+enum RawJsonPathSegment {
     Key(String),
-    Index(usize),
+    Index(isize),
+}
+
+impl From<RawJsonPathSegment> for JsonPathSegment {
+    fn from(segment: RawJsonPathSegment) -> Self {
+        match segment {
+            RawJsonPathSegment::Key(k) if k == "*" => JsonPathSegment::Wildcard,
+            RawJsonPathSegment::Key(k) if k == ".." => JsonPathSegment::Descendants,
+            RawJsonPathSegment::Key(k) if k == "-" || k == "[]" => JsonPathSegment::Append,
+            RawJsonPathSegment::Key(k) => JsonPathSegment::Key(k),
+            RawJsonPathSegment::Index(i) => JsonPathSegment::Index(i),
+        }
+    }
 }
 
 #[tokio::main]
@@ -177,22 +372,272 @@ async fn main() {
     }
     env_logger::init_from_env("FORWARDHOOK_LOG");
 
-    let config_path = match env::args().nth(1) {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let config_path = match args.first().map(String::as_str) {
+        Some("dead-letters") => return dead_letter::command(&args[1..]).await,
         Some(s) => s,
-        None => "forwardhook.json".to_owned(),
+        None => "forwardhook.json",
+    };
+    let config = Arc::new(load_config(config_path).await);
+    let client = Arc::new(client(&config));
+    let queue = match &config.data_dir {
+        Some(dir) => Some(
+            Queue::open(dir, config.clone(), client.clone())
+                .await
+                .unwrap_or_exit("Can't open delivery queue", 73),
+        ),
+        None => None,
     };
+    let port = config.port;
+
+    let admin = warp::path!("_admin" / "dead-letters" / ..)
+        .and(warp::method())
+        .and(warp::path::tail())
+        .and(warp::header::optional("authorization"))
+        .and(inject(config.admin_token.clone().map(Arc::new)))
+        .and(inject(queue.clone()))
+        .and_then(dead_letter::admin);
+    let webhooks = Request::filter()
+        .and(warp::body::bytes())
+        .and(inject(config))
+        .and(inject(client))
+        .and(inject(queue))
+        .and_then(handler);
+    let filter = admin
+        .or(webhooks)
+        .or(warp::any().map(|| warp::reply::with_status("Not Found", StatusCode::NOT_FOUND)));
+
+    warp::serve(filter.with(warp::log("forwardhook")))
+        .run(([127, 0, 0, 1], port))
+        .await;
+}
+
+async fn load_config(config_path: &str) -> Config {
     let config_contents = fs::read_to_string(&config_path)
         .await
         .unwrap_or_exit("Can't read config file", 74);
-    let config: Config =
+    let mut config: Config =
         serde_json::from_str(&config_contents).unwrap_or_exit("Invalid config file", 66);
-    let port = config.port;
 
-    for id in config.webhooks.keys() {
+    for (id, webhook) in &mut config.webhooks {
         debug!("/{}", id);
+
+        // The top level `forwardUrl` is shorthand for a single target
+        if let Some(url) = webhook.forward_url.take() {
+            let target = shorthand(
+                url,
+                webhook.forward_method,
+                webhook.forward_format,
+                mem::take(&mut webhook.forward_xml_root),
+                mem::take(&mut webhook.fields),
+            );
+            webhook.targets.insert(0, target);
+        } else if !webhook.fields.is_empty() {
+            eprintln!(
+                "Invalid config file: `fields` of `{}` need a `forwardUrl`, targets have their own",
+                id
+            );
+            process::exit(66);
+        }
+        // Targets of routes are moved to the webhook, so that queued jobs can refer to them
+        if !webhook.routes.is_empty() && !webhook.targets.is_empty() {
+            eprintln!(
+                "Invalid config file: `{}` can only have one of `routes` or targets",
+                id
+            );
+            process::exit(66);
+        }
+        let last = webhook.routes.len().saturating_sub(1);
+        for (i, route) in webhook.routes.iter_mut().enumerate() {
+            if let Some(url) = route.forward_url.take() {
+                let target = shorthand(
+                    url,
+                    route.forward_method,
+                    route.forward_format,
+                    mem::take(&mut route.forward_xml_root),
+                    mem::take(&mut route.fields),
+                );
+                route.targets.insert(0, target);
+            } else if !route.fields.is_empty() {
+                eprintln!(
+                    "Invalid config file: `fields` of route {} of `{}` need a `forwardUrl`, targets have their own",
+                    i, id
+                );
+                process::exit(66);
+            }
+            if route.targets.is_empty() {
+                eprintln!(
+                    "Invalid config file: no targets for route {} of `{}`",
+                    i, id
+                );
+                process::exit(66);
+            }
+            if route.when.is_none() && i != last {
+                eprintln!(
+                    "Invalid config file: only the last route of `{}` can omit `when`",
+                    id
+                );
+                process::exit(66);
+            }
+            let start = webhook.targets.len();
+            webhook.targets.append(&mut route.targets);
+            route.range = start..webhook.targets.len();
+        }
+        if webhook.targets.is_empty() {
+            eprintln!("Invalid config file: no targets for `{}`", id);
+            process::exit(66);
+        }
+        let mappings = [
+            webhook.transform.is_some(),
+            webhook.script.is_some(),
+            webhook.plugin.is_some(),
+            webhook.targets.iter().any(|t| !t.fields.is_empty()),
+        ];
+        if mappings.iter().filter(|m| **m).count() > 1 {
+            eprintln!(
+                "Invalid config file: `{}` can only have one of `transform`, `script`, `plugin` or `fields`",
+                id
+            );
+            process::exit(66);
+        }
+        let based = !matches!(webhook.base, Base::Empty) || !webhook.remove.is_empty();
+        if based && mappings[..3].iter().any(|m| *m) {
+            eprintln!(
+                "Invalid config file: `base` and `remove` of `{}` only apply to `fields`",
+                id
+            );
+            process::exit(66);
+        }
+        if webhook
+            .remove
+            .iter()
+            .flatten()
+            .any(|s| matches!(s, JsonPathSegment::Append))
+        {
+            eprintln!("Invalid config file: `{}` can't remove `-`", id);
+            process::exit(66);
+        }
+
+        for target in &mut webhook.targets {
+            if target.fields.iter().any(|f| {
+                [f.from.is_some(), f.value.is_some(), f.template.is_some()]
+                    .iter()
+                    .filter(|s| **s)
+                    .count()
+                    != 1
+            }) {
+                eprintln!(
+                    "Invalid config file: fields in `{}` need exactly one of `from`, `value` or `template`",
+                    id
+                );
+                process::exit(66);
+            }
+            if target.fields.iter().any(|f| {
+                f.to.iter()
+                    .any(|s| matches!(s, JsonPathSegment::Descendants | JsonPathSegment::Filter(_)))
+                    || f.from
+                        .iter()
+                        .flatten()
+                        .any(|s| matches!(s, JsonPathSegment::Append))
+            }) {
+                eprintln!(
+                    "Invalid config file: fields in `{}` can't write to `..` or filters, or read from `-`",
+                    id
+                );
+                process::exit(66);
+            }
+
+            for (name, value) in &webhook.headers {
+                // Header names are case-insensitive, and reqwest would send both
+                if !target.headers.keys().any(|n| n.eq_ignore_ascii_case(name)) {
+                    target.headers.insert(name.clone(), value.clone());
+                }
+            }
+            for (name, value) in &mut target.headers {
+                let valid = secret::interpolate(value).and_then(|v| {
+                    HeaderName::from_bytes(name.as_bytes()).map_err(|e| e.to_string())?;
+                    HeaderValue::from_str(&v).map_err(|e| e.to_string())?;
+                    Ok(v)
+                });
+                match valid {
+                    Ok(v) => *value = v,
+                    Err(e) => {
+                        eprintln!("Invalid config file: header `{}` in `{}`: {}", name, id, e);
+                        process::exit(66);
+                    }
+                }
+            }
+        }
+        // Queued deliveries and dead letters only remember the URL of their target
+        let targets = &webhook.targets;
+        let conflict = targets.iter().enumerate().find_map(|(i, t)| {
+            let j = targets[..i].iter().position(|o| {
+                o.url == t.url
+                    && (o.method, o.format, &o.xml_root, &o.headers)
+                        != (t.method, t.format, &t.xml_root, &t.headers)
+            })?;
+            Some((j, i))
+        });
+        if let Some((j, i)) = conflict {
+            eprintln!(
+                "Invalid config file: targets {} and {} of `{}` have the same URL but differ in method, format, xmlRoot or headers",
+                j, i, id
+            );
+            process::exit(66);
+        }
+        if webhook.queued && config.data_dir.is_none() {
+            eprintln!(
+                "Invalid config file: `{}` is queued but `dataDir` is missing",
+                id
+            );
+            process::exit(66);
+        }
+        // Queued deliveries are retried even without a `retry` block
+        if webhook.queued && webhook.retry.is_none() {
+            webhook.retry = Some(Retry::default());
+        }
+    }
+
+    config
+}
+
+impl Webhook {
+    /// Whether the forwarded JSON comes from a `transform`, `script` or `plugin`
+    fn is_programmed(&self) -> bool {
+        self.transform.is_some() || self.script.is_some() || self.plugin.is_some()
+    }
+
+    /// Runs the `transform`, `script` or `plugin` of the webhook, along with which one it is
+    fn run(&self, request: &Request) -> Option<(&'static str, Result<Option<JsonObject>, String>)> {
+        match (&self.transform, &self.script, &self.plugin) {
+            (Some(transform), _, _) => Some(("transform", transform.run(request).map(Some))),
+            (_, Some(script), _) => Some(("script", script.run(request))),
+            (_, _, Some(plugin)) => Some(("plugin", plugin.run(request))),
+            _ => None,
+        }
+    }
+}
+
+/// Builds the target a `forwardUrl` and its siblings are shorthand for
+fn shorthand(
+    url: String,
+    method: Method,
+    format: Format,
+    xml_root: String,
+    fields: Vec<Field>,
+) -> Target {
+    Target {
+        url,
+        method,
+        format,
+        xml_root,
+        fields,
+        headers: HashMap::new(),
     }
+}
 
-    let client = Client::builder()
+fn client(config: &Config) -> Client {
+    Client::builder()
         .user_agent(
             config
                 .user_agent
@@ -201,122 +646,738 @@ async fn main() {
                 .unwrap_or(concat!("forwardhook/", env!("CARGO_PKG_VERSION"))),
         )
         .build()
-        .unwrap_or_exit("Can't create web client", 65);
-
-    let filter = warp::path!(String)
-        .and(warp::body::json())
-        .and(inject(Arc::new(config)))
-        .and(inject(Arc::new(client)))
-        .and_then(handler)
-        .or(warp::any().map(|| warp::reply::with_status("Not Found", StatusCode::NOT_FOUND)));
-
-    warp::serve(filter.with(warp::log("forwardhook")))
-        .run(([127, 0, 0, 1], port))
-        .await;
+        .unwrap_or_exit("Can't create web client", 65)
 }
 
 async fn handler(
     id: String,
-    body: JsonObject,
+    headers: HeaderMap,
+    mut request: Request,
+    raw_body: Bytes,
     config: Arc<Config>,
     client: Arc<Client>,
-) -> Result<impl Reply, Rejection> {
-    let debug = config.debug;
-    let config = match config.webhooks.get(&id) {
-        Some(c) => c,
+    queue: Option<Arc<Queue>>,
+) -> Result<Response, Rejection> {
+    let webhook = match config.webhooks.get(&id) {
+        Some(w) => w,
         None => return Err(warp::reject::not_found()),
     };
-    let mut forwarded = JsonObject::new();
 
-    for field in &config.fields {
-        let from = match from(field, &body, &id) {
-            Ok(f) => f,
-            Err(e) => {
+    if let Some(verify) = &webhook.verify {
+        if let Err(e) = verify.verify(&headers, &raw_body) {
+            eprintln!("Unauthorized request to `{}`: {}", id, e);
+            return Ok(
+                warp::reply::with_status("Unauthorized", StatusCode::UNAUTHORIZED).into_response(),
+            );
+        }
+    }
+    request.body = body::parse(&headers, &raw_body)
+        .await
+        .or_log_and_reject(&format!("Invalid body in `{}`", id))?;
+
+    // Discord checks that interaction endpoints answer its PING handshake on their own
+    if let Some(Verify::Discord { .. }) = &webhook.verify {
+        if request.body.get("type") == Some(&JsonValue::from(1)) {
+            return Ok(warp::reply::json(&json!({ "type": 1 })).into_response());
+        }
+    }
+
+    let filtered = webhook.when.as_ref().is_some_and(|w| !w.matches(&request));
+    let targets = if webhook.routes.is_empty() {
+        0..webhook.targets.len()
+    } else {
+        let route = webhook
+            .routes
+            .iter()
+            .find(|r| r.when.as_ref().is_none_or(|w| w.matches(&request)));
+        match route {
+            Some(r) => r.range.clone(),
+            None => {
+                debug!("Dropping request to `{}` which doesn't match any route", id);
+                0..0
+            }
+        }
+    };
+    // jq filters, scripts and plugins can take a while, so they run off the async executor
+    let (request, output) = if filtered || !webhook.is_programmed() {
+        (request, None)
+    } else {
+        let (config, key) = (config.clone(), id.clone());
+        task::spawn_blocking(move || {
+            let output = config.webhooks[&key].run(&request);
+            (request, output)
+        })
+        .await
+        .or_log_and_reject(&format!("Can't transform `{}`", id))?
+    };
+    let payloads = match output {
+        _ if filtered => {
+            debug!("Dropping request to `{}` which doesn't match `when`", id);
+            Vec::new()
+        }
+        Some((_, Ok(Some(payload)))) => vec![payload; targets.len()],
+        // The script or plugin dropped the request, so there is nothing to forward
+        Some((_, Ok(None))) => Vec::new(),
+        Some((kind, Err(e))) => {
+            eprintln!("Can't run the {} of `{}`: {}", kind, id, e);
+            return Err(warp::reject());
+        }
+        None => {
+            let base = base(webhook, &request).map_err(|e| {
+                eprintln!("Can't build the base of `{}`: {}", id, e);
+                warp::reject()
+            })?;
+            let mut payloads = Vec::with_capacity(targets.len());
+            for target in &webhook.targets[targets.clone()] {
+                payloads.push(map(&target.fields, &request, &id, base.clone())?);
+            }
+            payloads
+        }
+    };
+
+    if config.debug {
+        return match payloads.as_slice() {
+            [forwarded] => Ok(warp::reply::json(forwarded).into_response()),
+            _ => Ok(warp::reply::json(&payloads).into_response()),
+        };
+    }
+
+    let delivered = match (webhook.policy, &queue) {
+        _ if payloads.is_empty() => true,
+        (_, Some(queue)) if webhook.queued => {
+            for (target, payload) in webhook.targets[targets].iter().zip(payloads) {
+                let job = Job {
+                    webhook: id.clone(),
+                    url: target.url.clone(),
+                    body: String::from_utf8_lossy(&raw_body).into_owned(),
+                    payload,
+                };
+                if let Err(e) = queue.push(&job).await {
+                    eprintln!("Can't queue `{}`: {}", id, e);
+                    return Ok(warp::reply::with_status(
+                        "Internal Server Error",
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    )
+                    .into_response());
+                }
+            }
+            true
+        }
+        (Policy::All, _) => forward::dispatch(&client, &id, webhook, targets, &payloads)
+            .await
+            .iter()
+            .all(Result::is_ok),
+        (Policy::Any, _) => forward::dispatch(&client, &id, webhook, targets, &payloads)
+            .await
+            .iter()
+            .any(Result::is_ok),
+        (Policy::FireAndForget, queue) => {
+            let config = config.clone();
+            let queue = queue.clone();
+            tokio::spawn(async move {
+                let webhook = &config.webhooks[&id];
+                let results =
+                    forward::dispatch(&client, &id, webhook, targets.clone(), &payloads).await;
+
+                // Nobody else is going to retry these, so keep them around when possible
+                let queue = match queue {
+                    Some(q) => q,
+                    None => return,
+                };
+                let targets = &webhook.targets[targets];
+                for (target, (result, payload)) in
+                    targets.iter().zip(results.into_iter().zip(payloads))
+                {
+                    if let Err(f) = result {
+                        let job = Job {
+                            webhook: id.clone(),
+                            url: target.url.clone(),
+                            body: String::from_utf8_lossy(&raw_body).into_owned(),
+                            payload,
+                        };
+                        let letter = DeadLetter::new(job, f.to_string(), f.attempts);
+                        if let Err(e) = queue.dead_letters.push(&letter).await {
+                            eprintln!("Can't store dead letter for `{}`: {}", id, e);
+                        }
+                    }
+                }
+            });
+            true
+        }
+    };
+    if !delivered {
+        return Ok(
+            warp::reply::with_status("Bad Gateway", StatusCode::BAD_GATEWAY).into_response(),
+        );
+    }
+
+    match &webhook.reply {
+        Some(o) => Ok(warp::reply::json(o).into_response()),
+        None => Ok(warp::reply::json(&JsonObject::new()).into_response()),
+    }
+}
+
+/// Builds the object the fields are written on top of
+fn base(webhook: &Webhook, request: &Request) -> Result<JsonObject, String> {
+    let mut base = match &webhook.base {
+        Base::Empty => return Ok(JsonObject::new()),
+        Base::Passthrough => request.body.clone(),
+        Base::From(path) => resolve(&request.body, path)?.into_owned(),
+    };
+    for path in &webhook.remove {
+        remove(&mut base, path);
+    }
+    match base {
+        JsonValue::Object(o) => Ok(o),
+        v => Err(format!("Expected object, got `{}`", v)),
+    }
+}
+
+fn map(
+    fields: &[Field],
+    request: &Request,
+    id: &str,
+    mut forwarded: JsonObject,
+) -> Result<JsonObject, Rejection> {
+    for field in fields {
+        if field.when.as_ref().is_some_and(|w| !w.matches(request)) {
+            continue;
+        }
+        let from = match (&field.value, &field.template) {
+            (Some(value), _) => Ok(Cow::Borrowed(value)),
+            (_, Some(template)) => template
+                .render(request, field.source)
+                .map(|rendered| Cow::Owned(rendered.into())),
+            _ => from(field, request),
+        };
+        let depth = wildcards(&field.to);
+        let from = match (from, &field.default) {
+            // Transforms apply to every value distributed by the wildcards of `to`, except the
+            // placeholders of the elements `from` couldn't be followed from
+            (Ok(f), _) => each(f.into_owned(), depth, &mut |mut value| {
+                if depth > 0 && value.is_null() {
+                    return Ok(value);
+                }
+                for transform in &field.transforms {
+                    value = transform.apply(value)?;
+                }
+                Ok(value)
+            })
+            .map_err(|e| {
+                eprintln!(
+                    "Can't transform field `{}` in `{}`: {}",
+                    display_path(&field.to),
+                    id,
+                    e
+                );
+                warp::reject()
+            })?,
+            // Defaults are written as is, without going through the transforms
+            (Err(_), Some(default)) => default.clone(),
+            (Err(e), None) => {
+                eprintln!("{} in `{}`", e, id);
                 if field.optional {
                     continue;
                 } else {
-                    return Err(e);
+                    return Err(warp::reject());
                 }
             }
         };
 
-        macro_rules! match_peek {
-            ($iter:expr) => {
-                match $iter.peek() {
-                    Some(JsonPathSegment::Key(_)) => OrInsertJsonValue::Object,
-                    Some(JsonPathSegment::Index(_)) => OrInsertJsonValue::Array,
-                    _ => OrInsertJsonValue::Null,
-                }
-            };
-        }
+        write(&mut forwarded, &field.to, from).map_err(|e| {
+            eprintln!(
+                "Can't write field `{}` in `{}`: {}",
+                display_path(&field.to),
+                id,
+                e
+            );
+            warp::reject()
+        })?;
+    }
 
-        let mut to_segments = field.to.iter().peekable();
-        let mut to = match to_segments.next() {
-            Some(JsonPathSegment::Key(k)) => {
-                forwarded.get_or_insert_mut(k, match_peek!(to_segments))
+    Ok(forwarded)
+}
+
+fn from<'a>(field: &Field, request: &'a Request) -> Result<Cow<'a, Value>, String> {
+    let path = field.from.as_deref().unwrap_or_default();
+    match resolve(request.root(field.source), path)? {
+        value if value.is_null() && field.null_as_missing => Err("Null value".to_owned()),
+        value => Ok(value),
+    }
+}
+
+fn display_path(path: &[JsonPathSegment]) -> String {
+    let segments: JsonArray = path
+        .iter()
+        .map(|s| match s {
+            JsonPathSegment::Key(k) => k.as_str().into(),
+            JsonPathSegment::Index(i) => (*i).into(),
+            JsonPathSegment::Wildcard => "*".into(),
+            JsonPathSegment::Descendants => "..".into(),
+            JsonPathSegment::Append => "-".into(),
+            JsonPathSegment::Filter(f) => f.to_string().into(),
+        })
+        .collect();
+    JsonValue::from(segments).to_string()
+}
+
+fn wildcards(path: &[JsonPathSegment]) -> usize {
+    path.iter()
+        .filter(|s| matches!(s, JsonPathSegment::Wildcard))
+        .count()
+}
+
+/// Follows a path from a value, describing the first segment which can't be followed on failure
+///
+/// Every wildcard, descendants or filter segment adds a level of arrays to the result. Wildcards keep
+/// `null` for the values the rest of the path can't be followed from, so that the result lines up with
+/// the array it comes from, while descendants and filters leave them out.
+fn resolve<'a>(mut value: &'a Value, path: &[JsonPathSegment]) -> Result<Cow<'a, Value>, String> {
+    for (i, segment) in path.iter().enumerate() {
+        let values: Vec<&Value> = match segment {
+            JsonPathSegment::Key(k) => {
+                value = value
+                    .as_object()
+                    .ok_or("Expected object")?
+                    .get(k)
+                    .ok_or_else(|| format!("Missing key `{}`", k))?;
+                continue;
             }
-            _ => return Err(warp::reject()),
-        };
-        while let Some(segment) = to_segments.next() {
-            to = match segment {
-                JsonPathSegment::Key(k) => {
-                    let obj = to.as_object_mut().or_reject()?;
-                    obj.get_or_insert_mut(k, match_peek!(to_segments))
-                }
-                JsonPathSegment::Index(i) => {
-                    let ary = to.as_array_mut().or_reject()?;
-                    ary.get_or_insert_mut(*i, match_peek!(to_segments))
+            // JSON Pointers can't tell indices from keys which look like numbers
+            JsonPathSegment::Index(i) if *i >= 0 && value.is_object() => {
+                value = value
+                    .get(i.to_string())
+                    .ok_or_else(|| format!("Missing key `{}`", i))?;
+                continue;
+            }
+            JsonPathSegment::Index(i) => {
+                let array = value.as_array().ok_or("Expected array")?;
+                value = index(array.len(), *i)
+                    .and_then(|i| array.get(i))
+                    .ok_or_else(|| format!("Missing index `{}`", i))?;
+                continue;
+            }
+            JsonPathSegment::Append => return Err("Can't read from `-`".to_owned()),
+            JsonPathSegment::Wildcard => match value {
+                Value::Array(a) => a.iter().collect(),
+                Value::Object(o) => o.values().collect(),
+                _ => return Err("Expected array or object".to_owned()),
+            },
+            JsonPathSegment::Filter(f) => match value {
+                Value::Array(a) => a.iter().filter(|v| f.matches(v)).collect(),
+                Value::Object(o) => o.values().filter(|v| f.matches(v)).collect(),
+                _ => return Err("Expected array or object".to_owned()),
+            },
+            JsonPathSegment::Descendants => {
+                let mut values = vec![value];
+                let mut i = 0;
+                while let Some(v) = values.get(i) {
+                    match v {
+                        Value::Array(a) => values.extend(a.iter()),
+                        Value::Object(o) => values.extend(o.values()),
+                        _ => (),
+                    }
+                    i += 1;
                 }
-            };
-        }
+                values
+            }
+        };
+        let rest = &path[i + 1..];
+        let aligned = matches!(segment, JsonPathSegment::Wildcard);
+        return Ok(Cow::Owned(
+            values
+                .into_iter()
+                .filter_map(|v| match resolve(v, rest) {
+                    Ok(v) => Some(v.into_owned()),
+                    Err(_) if aligned => Some(JsonValue::Null),
+                    Err(_) => None,
+                })
+                .collect(),
+        ));
+    }
+    Ok(Cow::Borrowed(value))
+}
 
-        *to = from.clone();
+/// Turns an index which can be negative into a position in an array of length `len`
+fn index(len: usize, i: isize) -> Option<usize> {
+    if i >= 0 {
+        Some(i as usize)
+    } else {
+        len.checked_sub(i.unsigned_abs())
     }
+}
 
-    if !debug {
-        match config.forward_method {
-            Method::Post => client.post(&config.forward_url),
-            Method::Put => client.put(&config.forward_url),
-            Method::Patch => client.patch(&config.forward_url),
+/// Applies `f` to every value a path with `depth` wildcards distributes
+fn each(
+    value: JsonValue,
+    depth: usize,
+    f: &mut impl FnMut(JsonValue) -> Result<JsonValue, String>,
+) -> Result<JsonValue, String> {
+    match (depth, value) {
+        (0, value) => f(value),
+        (_, JsonValue::Array(values)) => {
+            values.into_iter().map(|v| each(v, depth - 1, f)).collect()
         }
-        .json(&forwarded)
-        .send()
-        .await
-        .or_reject()?;
-        match &config.reply {
-            Some(o) => Ok(warp::reply::json(o)),
-            None => Ok(warp::reply::json(&JsonObject::new())),
+        (_, v) => Err(format!("Expected array for wildcard, got `{}`", v)),
+    }
+}
+
+/// Writes a value at a path, creating the objects and arrays leading to it
+///
+/// Wildcards write every element of an array to the matching index.
+fn write(
+    object: &mut JsonObject,
+    path: &[JsonPathSegment],
+    value: JsonValue,
+) -> Result<(), String> {
+    if let Some(i) = path
+        .iter()
+        .position(|s| matches!(s, JsonPathSegment::Wildcard))
+    {
+        let values = match value {
+            JsonValue::Array(values) => values,
+            v => return Err(format!("Expected array for wildcard, got `{}`", v)),
+        };
+        let array = slot(object, &path[..i], OrInsertJsonValue::Array)?;
+        if array.is_null() {
+            *array = JsonValue::Array(JsonArray::new());
         }
-    } else {
-        Ok(warp::reply::json(&forwarded))
+        let mut path = path.to_vec();
+        for (j, value) in values.into_iter().enumerate() {
+            path[i] = JsonPathSegment::Index(j as isize);
+            write(object, &path, value)?;
+        }
+        return Ok(());
     }
+
+    *slot(object, path, OrInsertJsonValue::Null)? = value;
+    Ok(())
 }
 
-fn from<'a, 'b, 'c>(
-    field: &'b Field,
-    body: &'a JsonObject,
-    id: &'c str,
-) -> Result<&'a Value, Rejection> {
-    let mut from_segments = field.from.iter();
-    let mut from = match from_segments.next() {
-        Some(JsonPathSegment::Key(k)) => body
-            .get(k)
-            .or_log_and_reject(&format!("Missing key `{}` in `{}`", k, id))?,
-        _ => return Err(warp::reject()),
+/// Finds the value at a path, inserting `last` if it doesn't exist yet
+fn slot<'a>(
+    object: &'a mut JsonObject,
+    path: &[JsonPathSegment],
+    last: OrInsertJsonValue,
+) -> Result<&'a mut JsonValue, String> {
+    macro_rules! match_peek {
+        ($iter:expr) => {
+            match $iter.peek() {
+                Some(JsonPathSegment::Key(_)) => OrInsertJsonValue::Object,
+                Some(JsonPathSegment::Index(_))
+                | Some(JsonPathSegment::Wildcard)
+                | Some(JsonPathSegment::Append) => OrInsertJsonValue::Array,
+                Some(JsonPathSegment::Descendants) | Some(JsonPathSegment::Filter(_)) => {
+                    OrInsertJsonValue::Null
+                }
+                None => last,
+            }
+        };
+    }
+
+    let mut segments = path.iter().peekable();
+    let mut to = match segments.next() {
+        Some(JsonPathSegment::Key(k)) => object.get_or_insert_mut(k, match_peek!(segments)),
+        _ => return Err("Expected a key first".to_owned()),
     };
-    for segment in from_segments {
-        from = match segment {
-            JsonPathSegment::Key(k) => from
-                .as_object()
-                .or_log_and_reject(&format!("Expected object in `{}`", id))?
-                .get(k)
-                .or_log_and_reject(&format!("Missing key `{}` in `{}`", k, id))?,
-            JsonPathSegment::Index(i) => from
-                .as_array()
-                .or_log_and_reject(&format!("Expected array in `{}`", id))?
-                .get(*i)
-                .or_log_and_reject(&format!("Missing index `{}` in `{}`", i, id))?,
+    while let Some(segment) = segments.next() {
+        to = match segment {
+            JsonPathSegment::Key(k) => {
+                let obj = to.as_object_mut().ok_or("Expected object")?;
+                obj.get_or_insert_mut(k, match_peek!(segments))
+            }
+            // JSON Pointers can't tell indices from keys which look like numbers
+            JsonPathSegment::Index(i) if *i >= 0 && to.is_object() => {
+                let obj = to.as_object_mut().unwrap();
+                obj.get_or_insert_mut(&i.to_string(), match_peek!(segments))
+            }
+            // Negative indices only write to existing elements
+            JsonPathSegment::Index(i) => {
+                let ary = to.as_array_mut().ok_or("Expected array")?;
+                let index = index(ary.len(), *i).ok_or_else(|| format!("Missing index `{}`", i))?;
+                ary.get_or_insert_mut(index, match_peek!(segments))
+            }
+            JsonPathSegment::Append => {
+                let ary = to.as_array_mut().ok_or("Expected array")?;
+                ary.push(match_peek!(segments).concrete());
+                ary.last_mut().unwrap()
+            }
+            _ => return Err("Can't write to a wildcard or descendants".to_owned()),
         };
     }
-    Ok(from)
+    Ok(to)
+}
+
+/// Removes every value a path leads to, ignoring the parts of it which can't be followed
+fn remove(value: &mut JsonValue, path: &[JsonPathSegment]) {
+    let (segment, rest) = match path.split_first() {
+        Some(s) => s,
+        None => return,
+    };
+    match (value, segment) {
+        (JsonValue::Object(o), JsonPathSegment::Key(k)) if rest.is_empty() => {
+            o.shift_remove(k);
+        }
+        (JsonValue::Object(o), JsonPathSegment::Key(k)) => {
+            if let Some(v) = o.get_mut(k) {
+                remove(v, rest);
+            }
+        }
+        // JSON Pointers can't tell indices from keys which look like numbers
+        (JsonValue::Object(o), JsonPathSegment::Index(i)) if *i >= 0 => {
+            let key = i.to_string();
+            match o.get_mut(&key) {
+                Some(_) if rest.is_empty() => {
+                    o.shift_remove(&key);
+                }
+                Some(v) => remove(v, rest),
+                None => (),
+            }
+        }
+        (JsonValue::Array(a), JsonPathSegment::Index(i)) => match index(a.len(), *i) {
+            Some(i) if i < a.len() && rest.is_empty() => {
+                a.remove(i);
+            }
+            Some(i) if i < a.len() => remove(&mut a[i], rest),
+            _ => (),
+        },
+        (JsonValue::Object(o), JsonPathSegment::Wildcard) if rest.is_empty() => o.clear(),
+        (JsonValue::Array(a), JsonPathSegment::Wildcard) if rest.is_empty() => a.clear(),
+        (JsonValue::Object(o), JsonPathSegment::Filter(f)) if rest.is_empty() => {
+            o.retain(|_, v| !f.matches(v))
+        }
+        (JsonValue::Array(a), JsonPathSegment::Filter(f)) if rest.is_empty() => {
+            a.retain(|v| !f.matches(v))
+        }
+        (JsonValue::Object(o), JsonPathSegment::Wildcard) => {
+            o.values_mut().for_each(|v| remove(v, rest))
+        }
+        (JsonValue::Array(a), JsonPathSegment::Wildcard) => {
+            a.iter_mut().for_each(|v| remove(v, rest))
+        }
+        (JsonValue::Object(o), JsonPathSegment::Filter(f)) => o
+            .values_mut()
+            .filter(|v| f.matches(v))
+            .for_each(|v| remove(v, rest)),
+        (JsonValue::Array(a), JsonPathSegment::Filter(f)) => a
+            .iter_mut()
+            .filter(|v| f.matches(v))
+            .for_each(|v| remove(v, rest)),
+        // The rest of the path is removed from the value itself and from every value nested in it
+        (value, JsonPathSegment::Descendants) => {
+            remove(value, rest);
+            match value {
+                JsonValue::Array(a) => a.iter_mut().for_each(|v| remove(v, path)),
+                JsonValue::Object(o) => o.values_mut().for_each(|v| remove(v, path)),
+                _ => (),
+            }
+        }
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(s: &str) -> JsonPath {
+        path::parse(s).unwrap()
+    }
+
+    fn resolved(value: &JsonValue, s: &str) -> Result<JsonValue, String> {
+        resolve(value, &path(s)).map(Cow::into_owned)
+    }
+
+    fn written(fields: &[(&str, JsonValue)]) -> Result<JsonValue, String> {
+        let mut object = JsonObject::new();
+        for (to, value) in fields {
+            write(&mut object, &path(to), value.clone())?;
+        }
+        Ok(object.into())
+    }
+
+    fn removed(mut value: JsonValue, s: &str) -> JsonValue {
+        remove(&mut value, &path(s));
+        value
+    }
+
+    #[test]
+    fn resolve_keys() {
+        let body = json!({ "a": { "b": [1, 2], "0": "zero" } });
+        assert_eq!(resolved(&body, "$.a.b[1]"), Ok(json!(2)));
+        assert_eq!(resolved(&body, "/a/0"), Ok(json!("zero")));
+        assert_eq!(resolved(&body, "$.a.c"), Err("Missing key `c`".to_owned()));
+        assert_eq!(
+            resolved(&body, "$.a.b.c"),
+            Err("Expected object".to_owned())
+        );
+    }
+
+    #[test]
+    fn resolve_wildcards_keep_alignment() {
+        let body = json!({
+            "commits": [
+                { "message": "a" },
+                { "message": "b", "author": { "name": "bob" } },
+            ]
+        });
+        assert_eq!(
+            resolved(&body, "$.commits[*].message"),
+            Ok(json!(["a", "b"]))
+        );
+        assert_eq!(
+            resolved(&body, "$.commits[*].author.name"),
+            Ok(json!([null, "bob"]))
+        );
+        assert_eq!(
+            resolved(&json!({ "a": [[{ "b": 1 }, {}], []] }), "$.a[*][*].b"),
+            Ok(json!([[1, null], []]))
+        );
+        assert_eq!(
+            resolved(&body, "$.commits[0].message[*]"),
+            Err("Expected array or object".to_owned())
+        );
+    }
+
+    #[test]
+    fn resolve_descendants_leave_out_missing() {
+        let body = json!({ "id": 1, "a": { "id": 2, "b": [{ "id": 3 }, { "name": "x" }] } });
+        assert_eq!(resolved(&body, "$..id"), Ok(json!([1, 2, 3])));
+        assert_eq!(resolved(&body, "$.a.b[*].id"), Ok(json!([3, null])));
+    }
+
+    #[test]
+    fn resolve_filters_leave_out_missing() {
+        let body = json!({ "a": { "b": [{ "id": 3 }, { "name": "x" }] } });
+        assert_eq!(resolved(&body, "$.a.b[?(@.id)].id"), Ok(json!([3])));
+        assert_eq!(resolved(&body, "$.a.b[?(@.id > 5)]"), Ok(json!([])));
+    }
+
+    #[test]
+    fn write_wildcards_line_up() {
+        let object = written(&[
+            ("$.lines[*].text", json!(["a", "b"])),
+            ("$.lines[*].author", json!([null, "bob"])),
+        ]);
+        assert_eq!(
+            object,
+            Ok(json!({
+                "lines": [
+                    { "text": "a", "author": null },
+                    { "text": "b", "author": "bob" },
+                ]
+            }))
+        );
+        assert_eq!(
+            written(&[("$.grid[*][*]", json!([[1, 2], [3]]))]),
+            Ok(json!({ "grid": [[1, 2], [3]] }))
+        );
+        assert_eq!(
+            written(&[("$.lines[*]", json!("a"))]),
+            Err("Expected array for wildcard, got `\"a\"`".to_owned())
+        );
+    }
+
+    #[test]
+    fn write_creates_containers() {
+        assert_eq!(
+            written(&[("$.a.b", json!(1)), ("$.a.c[1].d", json!(2))]),
+            Ok(json!({ "a": { "b": 1, "c": [null, { "d": 2 }] } }))
+        );
+        assert_eq!(
+            written(&[("$.a", json!(1)), ("$.a.b", json!(2))]),
+            Err("Expected object".to_owned())
+        );
+        assert_eq!(
+            written(&[("$[0]", json!(1))]),
+            Err("Expected a key first".to_owned())
+        );
+    }
+
+    #[test]
+    fn negative_indices() {
+        let body = json!({ "commits": [1, 2, 3] });
+        assert_eq!(resolved(&body, "$.commits[-1]"), Ok(json!(3)));
+        assert_eq!(resolved(&body, "$.commits[-3]"), Ok(json!(1)));
+        assert_eq!(
+            resolved(&body, "$.commits[-4]"),
+            Err("Missing index `-4`".to_owned())
+        );
+
+        let mut object = json!({ "a": [1, 2] }).as_object().unwrap().clone();
+        write(&mut object, &path("$.a[-1]"), json!(3)).unwrap();
+        assert_eq!(JsonValue::from(object.clone()), json!({ "a": [1, 3] }));
+        assert_eq!(
+            write(&mut object, &path("$.a[-3]"), json!(0)),
+            Err("Missing index `-3`".to_owned())
+        );
+        assert_eq!(removed(json!([1, 2, 3]), "/-1"), json!([1, 2, 3]));
+        assert_eq!(
+            removed(json!({ "a": [1, 2, 3] }), "$.a[-1]"),
+            json!({ "a": [1, 2] })
+        );
+    }
+
+    #[test]
+    fn write_past_the_end() {
+        assert_eq!(
+            written(&[("/a/0", json!(1)), ("/a/1", json!(2)), ("/a/3", json!(4))]),
+            Ok(json!({ "a": [1, 2, null, 4] }))
+        );
+    }
+
+    #[test]
+    fn write_numeric_keys() {
+        assert_eq!(
+            written(&[("/a/b", json!(1)), ("/a/0", json!(2)), ("/a/1/c", json!(3))]),
+            Ok(json!({ "a": { "b": 1, "0": 2, "1": { "c": 3 } } }))
+        );
+        assert_eq!(
+            written(&[("/a/b", json!(1)), ("$.a[-1]", json!(2))]),
+            Err("Expected array".to_owned())
+        );
+    }
+
+    #[test]
+    fn append() {
+        assert_eq!(
+            written(&[("/a/-", json!(1)), ("/a/-", json!(2)), ("/a/-", json!(3))]),
+            Ok(json!({ "a": [1, 2, 3] }))
+        );
+        assert_eq!(
+            written(&[("/a/-/b", json!(1)), ("/a/-/b", json!(2))]),
+            Ok(json!({ "a": [{ "b": 1 }, { "b": 2 }] }))
+        );
+        assert_eq!(
+            resolved(&json!({ "a": [] }), "/a/-"),
+            Err("Can't read from `-`".to_owned())
+        );
+    }
+
+    #[test]
+    fn remove_paths() {
+        let body = json!({
+            "a": 1,
+            "b": { "c": 2, "d": 3 },
+            "e": [{ "f": 1, "g": 2 }, { "f": 3 }],
+            "h": { "i": { "f": 4 } },
+        });
+        assert_eq!(
+            removed(body.clone(), "$.b.c"),
+            json!({ "a": 1, "b": { "d": 3 }, "e": [{ "f": 1, "g": 2 }, { "f": 3 }], "h": { "i": { "f": 4 } } })
+        );
+        assert_eq!(
+            removed(body.clone(), "$.e[*].f"),
+            json!({ "a": 1, "b": { "c": 2, "d": 3 }, "e": [{ "g": 2 }, {}], "h": { "i": { "f": 4 } } })
+        );
+        assert_eq!(
+            removed(body.clone(), "$..f"),
+            json!({ "a": 1, "b": { "c": 2, "d": 3 }, "e": [{ "g": 2 }, {}], "h": { "i": {} } })
+        );
+        assert_eq!(
+            removed(body.clone(), "$.e[?(@.g)]"),
+            json!({ "a": 1, "b": { "c": 2, "d": 3 }, "e": [{ "f": 3 }], "h": { "i": { "f": 4 } } })
+        );
+        assert_eq!(removed(body.clone(), "$.x.y"), body);
+    }
 }
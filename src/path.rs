@@ -0,0 +1,418 @@
+use crate::{condition::compare, resolve, JsonPath, JsonPathSegment};
+use serde::{de::Error as _, Deserialize, Deserializer};
+use serde_json::Value as JsonValue;
+
+/// Deserializes a path written as an array of segments, a JSON Pointer or a JSONPath
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<JsonPath, D::Error> {
+    match JsonValue::deserialize(deserializer)? {
+        JsonValue::String(s) => parse(&s).map_err(|e| D::Error::custom(format!("`{}`: {}", s, e))),
+        value => serde_json::from_value(value).map_err(D::Error::custom),
+    }
+}
+
+pub fn deserialize_optional<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<JsonPath>, D::Error> {
+    deserialize(deserializer).map(Some)
+}
+
+/// Parses a JSON Pointer (`/todos/0/description`) or a JSONPath (`$.todos[0].description`)
+pub fn parse(s: &str) -> Result<JsonPath, String> {
+    if s.is_empty() || s.starts_with('/') {
+        Ok(pointer(s))
+    } else if let Some(rest) = s.strip_prefix('$') {
+        json_path(rest)
+    } else {
+        Err("expected a JSON Pointer starting with `/` or a JSONPath starting with `$`".to_owned())
+    }
+}
+
+fn pointer(s: &str) -> JsonPath {
+    s.split('/')
+        .skip(1)
+        .map(|segment| {
+            let segment = segment.replace("~1", "/").replace("~0", "~");
+            match segment.as_str() {
+                "-" => JsonPathSegment::Append,
+                // Indices can't have leading zeros, anything else is a key
+                s if s.bytes().all(|b| b.is_ascii_digit()) && (s == "0" || !s.starts_with('0')) => {
+                    s.parse()
+                        .map(JsonPathSegment::Index)
+                        .unwrap_or(JsonPathSegment::Key(segment))
+                }
+                _ => JsonPathSegment::Key(segment),
+            }
+        })
+        .collect()
+}
+
+/// Parses a JSONPath following its root, `$` or `@`
+fn json_path(mut rest: &str) -> Result<JsonPath, String> {
+    let mut path = Vec::new();
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("..") {
+            path.push(JsonPathSegment::Descendants);
+            rest = after;
+            if rest.starts_with('[') {
+                continue;
+            }
+            rest = name(rest, &mut path)?;
+        } else if let Some(after) = rest.strip_prefix('.') {
+            rest = name(after, &mut path)?;
+        } else if let Some(after) = rest.strip_prefix('[') {
+            rest = bracket(after.trim_start(), &mut path)?;
+        } else {
+            return Err(format!("unexpected `{}`", rest));
+        }
+    }
+    Ok(path)
+}
+
+/// Parses a `.name` segment following its dot
+fn name<'a>(s: &'a str, path: &mut JsonPath) -> Result<&'a str, String> {
+    let end = s.find(['.', '[']).unwrap_or(s.len());
+    let (name, rest) = s.split_at(end);
+    path.push(match name {
+        "" => return Err("expected a name after `.`".to_owned()),
+        "*" => JsonPathSegment::Wildcard,
+        name => JsonPathSegment::Key(name.to_owned()),
+    });
+    Ok(rest)
+}
+
+/// Parses a bracketed segment following its opening `[`
+fn bracket<'a>(s: &'a str, path: &mut JsonPath) -> Result<&'a str, String> {
+    let (segment, rest) = if let Some(after) = s.strip_prefix('?') {
+        let after = after
+            .trim_start()
+            .strip_prefix('(')
+            .ok_or("expected `(` after `?`")?;
+        let end = closing(after, ')').ok_or("expected `)` after filter")?;
+        let filter = Filter::parse(&after[..end])?;
+        (JsonPathSegment::Filter(filter), &after[end + 1..])
+    } else if s.starts_with('\'') || s.starts_with('"') {
+        let (key, rest) = quoted(s)?;
+        (JsonPathSegment::Key(key), rest)
+    } else {
+        let end = s.find(']').ok_or("expected `]`")?;
+        let segment = match s[..end].trim() {
+            "*" => JsonPathSegment::Wildcard,
+            index => JsonPathSegment::Index(
+                index
+                    .parse()
+                    .map_err(|_| format!("invalid index `{}`", index))?,
+            ),
+        };
+        (segment, &s[end..])
+    };
+    path.push(segment);
+    rest.trim_start()
+        .strip_prefix(']')
+        .ok_or_else(|| "expected `]`".to_owned())
+}
+
+/// Parses a string quoted with `'` or `"`, returning what follows it
+fn quoted(s: &str) -> Result<(String, &str), String> {
+    let mut chars = s.char_indices();
+    let quote = chars.next().map(|(_, c)| c);
+    let mut unquoted = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => unquoted.extend(chars.next().map(|(_, c)| c)),
+            c if Some(c) == quote => return Ok((unquoted, &s[i + 1..])),
+            c => unquoted.push(c),
+        }
+    }
+    Err("unterminated string".to_owned())
+}
+
+/// Finds the first `target` which isn't inside of a string or nested parentheses
+fn closing(s: &str, target: char) -> Option<usize> {
+    let mut quote = None;
+    let mut escaped = false;
+    let mut depth = 0;
+    s.find(|c| {
+        match (quote, c) {
+            _ if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => (),
+            (None, '\'') | (None, '"') => quote = Some(c),
+            (None, c) if c == target && depth == 0 => return true,
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            _ => (),
+        }
+        false
+    })
+}
+
+/// Splits on every `separator` which isn't inside of a string or parentheses
+fn split<'a>(mut s: &'a str, separator: &str) -> Vec<&'a str> {
+    let mut parts = Vec::new();
+    let first = separator.chars().next().unwrap();
+    loop {
+        let mut offset = 0;
+        let end = loop {
+            match closing(&s[offset..], first) {
+                Some(i) if s[offset + i..].starts_with(separator) => break Some(offset + i),
+                Some(i) => offset += i + first.len_utf8(),
+                None => break None,
+            }
+        };
+        match end {
+            Some(end) => {
+                parts.push(&s[..end]);
+                s = &s[end + separator.len()..];
+            }
+            None => {
+                parts.push(s);
+                return parts;
+            }
+        }
+    }
+}
+
+/// Condition of a `[?(...)]` JSONPath segment, keeping the elements for which it holds
+#[derive(Clone)]
+pub struct Filter {
+    source: String,
+    /// Alternatives of `||`, each of which is a list of comparisons joined by `&&`
+    alternatives: Vec<Vec<Comparison>>,
+}
+
+#[derive(Clone)]
+struct Comparison {
+    path: JsonPath,
+    /// Without an operator, the value only needs to exist and not be `false` or `null`
+    operation: Option<(Operator, JsonValue)>,
+}
+
+#[derive(Clone, Copy)]
+enum Operator {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Filter {
+    fn parse(s: &str) -> Result<Self, String> {
+        let alternatives = split(s, "||")
+            .into_iter()
+            .map(|a| split(a, "&&").into_iter().map(Comparison::parse).collect())
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            source: s.trim().to_owned(),
+            alternatives,
+        })
+    }
+
+    pub fn matches(&self, value: &JsonValue) -> bool {
+        self.alternatives
+            .iter()
+            .any(|a| a.iter().all(|c| c.matches(value)))
+    }
+}
+
+impl std::fmt::Display for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "?({})", self.source)
+    }
+}
+
+impl Comparison {
+    fn parse(s: &str) -> Result<Self, String> {
+        const OPERATORS: [(&str, Operator); 6] = [
+            ("==", Operator::Eq),
+            ("!=", Operator::Ne),
+            ("<=", Operator::Le),
+            (">=", Operator::Ge),
+            ("<", Operator::Lt),
+            (">", Operator::Gt),
+        ];
+
+        let s = s.trim();
+        let position = ['=', '!', '<', '>']
+            .iter()
+            .filter_map(|c| closing(s, *c))
+            .min();
+        let (path, operation) = match position {
+            Some(i) => {
+                let (name, operator) = OPERATORS
+                    .iter()
+                    .find(|(name, _)| s[i..].starts_with(name))
+                    .ok_or_else(|| format!("unknown operator in `{}`", s))?;
+                let literal = s[i + name.len()..].trim();
+                let literal = if literal.starts_with('\'') {
+                    match quoted(literal)? {
+                        (string, "") => string.into(),
+                        _ => return Err(format!("unexpected characters after `{}`", literal)),
+                    }
+                } else {
+                    serde_json::from_str(literal)
+                        .map_err(|_| format!("invalid literal `{}`", literal))?
+                };
+                (s[..i].trim_end(), Some((*operator, literal)))
+            }
+            None => (s, None),
+        };
+
+        let path = path
+            .strip_prefix('@')
+            .ok_or_else(|| format!("expected `@` in `{}`", s))?;
+        Ok(Self {
+            path: json_path(path)?,
+            operation,
+        })
+    }
+
+    fn matches(&self, value: &JsonValue) -> bool {
+        let value = match resolve(value, &self.path) {
+            Ok(v) => v,
+            Err(_) => return false,
+        };
+        let (operator, literal) = match &self.operation {
+            Some(o) => o,
+            None => return !matches!(*value, JsonValue::Null | JsonValue::Bool(false)),
+        };
+
+        match (operator, compare(&value, literal)) {
+            (Operator::Eq, o) => o.is_some_and(|o| o.is_eq()),
+            (Operator::Ne, o) => !o.is_some_and(|o| o.is_eq()),
+            (_, None) => false,
+            (Operator::Lt, Some(o)) => o.is_lt(),
+            (Operator::Le, Some(o)) => o.is_le(),
+            (Operator::Gt, Some(o)) => o.is_gt(),
+            (Operator::Ge, Some(o)) => o.is_ge(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display_path;
+    use serde_json::json;
+
+    fn parsed(s: &str) -> String {
+        display_path(&parse(s).unwrap())
+    }
+
+    fn filter(s: &str) -> Filter {
+        match parse(s).unwrap().pop() {
+            Some(JsonPathSegment::Filter(f)) => f,
+            _ => panic!("`{}` doesn't end with a filter", s),
+        }
+    }
+
+    #[test]
+    fn pointers() {
+        assert_eq!(parsed(""), "[]");
+        assert_eq!(
+            parsed("/todos/0/description"),
+            r#"["todos",0,"description"]"#
+        );
+        assert_eq!(parsed("/a~1b/~0c/~01"), r#"["a/b","~c","~1"]"#);
+        assert_eq!(parsed("/01/-1/-"), r#"["01","-1","-"]"#);
+        assert_eq!(parsed("/"), r#"[""]"#);
+    }
+
+    #[test]
+    fn json_paths() {
+        assert_eq!(parsed("$"), "[]");
+        assert_eq!(
+            parsed("$.todos[0].description"),
+            r#"["todos",0,"description"]"#
+        );
+        assert_eq!(parsed("$.todos[*].name"), r#"["todos","*","name"]"#);
+        assert_eq!(parsed("$.todos.*"), r#"["todos","*"]"#);
+        assert_eq!(parsed("$.commits[ -1 ]"), r#"["commits",-1]"#);
+        assert_eq!(parsed("$..id"), r#"["..","id"]"#);
+        assert_eq!(parsed("$..[0]"), r#"["..",0]"#);
+    }
+
+    #[test]
+    fn quoted_keys() {
+        assert_eq!(parsed("$['a.b']"), r#"["a.b"]"#);
+        assert_eq!(parsed(r#"$["c]d"]['[e']"#), r#"["c]d","[e"]"#);
+        assert_eq!(
+            parsed(r#"$['it\'s']["say \"hi\""]"#),
+            r#"["it's","say \"hi\""]"#
+        );
+        assert_eq!(parsed("$['0']"), r#"["0"]"#);
+    }
+
+    #[test]
+    fn filters() {
+        assert_eq!(
+            parsed("$.todos[?(@.done == false && @.priority >= 2)].name"),
+            r#"["todos","?(@.done == false && @.priority >= 2)","name"]"#
+        );
+
+        let f = filter("$[?(@.done == false && @.priority >= 2 || @.urgent)]");
+        assert!(f.matches(&json!({ "done": false, "priority": 2 })));
+        assert!(!f.matches(&json!({ "done": false, "priority": 1 })));
+        assert!(!f.matches(&json!({ "done": true, "priority": 3 })));
+        assert!(f.matches(&json!({ "done": true, "urgent": true })));
+        assert!(!f.matches(&json!({ "done": true, "urgent": null })));
+
+        let f = filter("$[?(@.a != 1 && @.b < 'm' && @.c > 0 && @.d <= 1.5)]");
+        assert!(f.matches(&json!({ "a": 2, "b": "a", "c": 1, "d": 1.5 })));
+        assert!(!f.matches(&json!({ "a": 1, "b": "a", "c": 1, "d": 1.5 })));
+        assert!(!f.matches(&json!({ "a": 2, "b": "z", "c": 1, "d": 1.5 })));
+        assert!(!f.matches(&json!({ "a": 2, "b": "a", "d": 1.5 })));
+    }
+
+    #[test]
+    fn quoted_operators() {
+        let f =
+            filter(r#"$[?(@.op == '&&' || @.op == "||" || @.op == 'a)b' || @['x == y'] == 1)]"#);
+        assert!(f.matches(&json!({ "op": "&&" })));
+        assert!(f.matches(&json!({ "op": "||" })));
+        assert!(f.matches(&json!({ "op": "a)b" })));
+        assert!(f.matches(&json!({ "x == y": 1 })));
+        assert!(!f.matches(&json!({ "op": "&" })));
+
+        let f = filter(r#"$[?(@.name == 'it\'s && more')]"#);
+        assert!(f.matches(&json!({ "name": "it's && more" })));
+    }
+
+    #[test]
+    fn splitting() {
+        assert_eq!(
+            split("a && 'b && c' && (d && e) && \"f && g\"", "&&"),
+            ["a ", " 'b && c' ", " (d && e) ", " \"f && g\""]
+        );
+        assert_eq!(split("a & b", "&&"), ["a & b"]);
+        assert_eq!(closing("'a)' (b)) c", ')'), Some(8));
+        assert_eq!(closing(r"'a\')' )", ')'), Some(7));
+        assert_eq!(closing("(a", ')'), None);
+    }
+
+    #[test]
+    fn invalid() {
+        for s in &[
+            "todos",
+            "$.",
+            "$.a..",
+            "$a",
+            "$[",
+            "$[0",
+            "$[x]",
+            "$['a",
+            "$['a'",
+            "$[?@.a]",
+            "$[?(@.a == 1]",
+            "$[?(@.a == )]",
+            "$[?(@.a === 1)]",
+            "$[?(@.a =! 1)]",
+            "$[?(@.a == 'b' c)]",
+            "$[?(a == 1)]",
+        ] {
+            assert!(parse(s).is_err(), "`{}` should be invalid", s);
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use crate::{request::Request, JsonObject};
+use serde::Deserialize;
+use serde_json::{json, Value as JsonValue};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+};
+use wasmi::{Config, Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+/// Engine shared by every plugin, so that compiled modules can be reused
+fn engine() -> &'static Engine {
+    static ENGINE: OnceLock<Engine> = OnceLock::new();
+    ENGINE.get_or_init(|| Engine::new(Config::default().consume_fuel(true)))
+}
+
+/// Compiled modules by path, so that webhooks sharing a plugin only compile it once
+fn compile(path: &Path) -> Result<Arc<Module>, String> {
+    static MODULES: OnceLock<Mutex<HashMap<PathBuf, Arc<Module>>>> = OnceLock::new();
+
+    let path = fs::canonicalize(path).map_err(|e| format!("`{}`: {}", path.display(), e))?;
+    let mut modules = MODULES.get_or_init(Default::default).lock().unwrap();
+    if let Some(module) = modules.get(&path) {
+        return Ok(module.clone());
+    }
+    let wasm = fs::read(&path).map_err(|e| format!("`{}`: {}", path.display(), e))?;
+    let module =
+        Module::new(engine(), &wasm).map_err(|e| format!("`{}`: {}", path.display(), e))?;
+    let module = Arc::new(module);
+    modules.insert(path, module.clone());
+    Ok(module)
+}
+
+/// A WebAssembly module exporting `memory`, `alloc(len: i32) -> i32` and
+/// `transform(ptr: i32, len: i32) -> i64`
+///
+/// `transform` receives the request as JSON and returns the location of its JSON output, with the
+/// pointer in the upper 32 bits and the length in the lower ones.
+#[derive(Deserialize)]
+#[serde(try_from = "PluginConfig")]
+pub struct Plugin {
+    module: Arc<Module>,
+    fuel: u64,
+    max_memory: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PluginConfig {
+    Path(PathBuf),
+    #[serde(rename_all = "camelCase")]
+    Options {
+        path: PathBuf,
+        #[serde(default = "default_fuel")]
+        fuel: u64,
+        /// Bytes
+        #[serde(default = "default_max_memory")]
+        max_memory: usize,
+    },
+}
+
+fn default_fuel() -> u64 {
+    10_000_000
+}
+fn default_max_memory() -> usize {
+    16 * 1024 * 1024
+}
+
+impl TryFrom<PluginConfig> for Plugin {
+    type Error = String;
+
+    fn try_from(config: PluginConfig) -> Result<Self, Self::Error> {
+        let (path, fuel, max_memory) = match config {
+            PluginConfig::Path(path) => (path, default_fuel(), default_max_memory()),
+            PluginConfig::Options {
+                path,
+                fuel,
+                max_memory,
+            } => (path, fuel, max_memory),
+        };
+        let module = compile(&path)?;
+
+        let exports: Vec<&str> = module.exports().map(|e| e.name()).collect();
+        for export in &["memory", "alloc", "transform"] {
+            if !exports.contains(export) {
+                return Err(format!("`{}` doesn't export `{}`", path.display(), export));
+            }
+        }
+
+        Ok(Self {
+            module,
+            fuel,
+            max_memory,
+        })
+    }
+}
+
+impl Plugin {
+    /// Runs the plugin in a fresh instance, returning `None` if it outputs `null` to drop the
+    /// request
+    ///
+    /// Plugins output the forwarded object, `null` or a string describing an error.
+    pub fn run(&self, request: &Request) -> Result<Option<JsonObject>, String> {
+        let input = json!({
+            "body": request.body,
+            "headers": request.headers,
+            "query": request.query,
+            "path": request.path,
+            "meta": request.meta,
+        })
+        .to_string();
+
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(self.max_memory)
+            .instances(1)
+            .build();
+        let mut store: Store<StoreLimits> = Store::new(engine(), limits);
+        store.limiter(|limits| limits);
+        store.set_fuel(self.fuel).map_err(|e| e.to_string())?;
+
+        // No host functions are linked, so plugins can't reach anything outside of their memory
+        let instance = Linker::new(engine())
+            .instantiate(&mut store, &self.module)
+            .and_then(|i| i.start(&mut store))
+            .map_err(|e| e.to_string())?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or("`memory` isn't a memory")?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&store, "alloc")
+            .map_err(|e| format!("`alloc`: {}", e))?;
+        let transform = instance
+            .get_typed_func::<(i32, i32), i64>(&store, "transform")
+            .map_err(|e| format!("`transform`: {}", e))?;
+
+        let len = i32::try_from(input.len()).map_err(|_| "request too large")?;
+        let ptr = alloc.call(&mut store, len).map_err(|e| e.to_string())?;
+        memory
+            .write(&mut store, ptr as u32 as usize, input.as_bytes())
+            .map_err(|e| e.to_string())?;
+        let output = transform
+            .call(&mut store, (ptr, len))
+            .map_err(|e| e.to_string())? as u64;
+
+        let (ptr, len) = ((output >> 32) as usize, output as u32 as usize);
+        let output = memory
+            .data(&store)
+            .get(ptr..ptr + len)
+            .ok_or("output out of bounds")?;
+        match serde_json::from_slice(output).map_err(|e| e.to_string())? {
+            JsonValue::Object(o) => Ok(Some(o)),
+            JsonValue::Null => Ok(None),
+            JsonValue::String(e) => Err(e),
+            v => Err(format!(
+                "expected an object, `null` or a string, got `{}`",
+                v
+            )),
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use crate::{
+    dead_letter::{DeadLetter, DeadLetters},
+    forward, Config, JsonObject,
+};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{fs, io::AsyncWriteExt, sync::mpsc};
+
+/// A payload waiting to be delivered to one of the targets of a webhook
+#[derive(Serialize, Deserialize)]
+pub struct Job {
+    pub webhook: String,
+    /// URL of the target, which unlike its position survives changes to the config
+    pub url: String,
+    /// Original body of the incoming request
+    #[serde(default)]
+    pub body: String,
+    pub payload: JsonObject,
+}
+
+/// Durable delivery queue, storing one file per pending job
+pub struct Queue {
+    dir: PathBuf,
+    sender: mpsc::UnboundedSender<PathBuf>,
+    pub dead_letters: Arc<DeadLetters>,
+}
+
+impl Queue {
+    /// Opens the queue in `data_dir` and starts delivering the jobs it already contains
+    pub async fn open(
+        data_dir: &Path,
+        config: Arc<Config>,
+        client: Arc<Client>,
+    ) -> io::Result<Arc<Self>> {
+        let dir = data_dir.join("queue");
+        fs::create_dir_all(&dir).await?;
+        let dead_letters = Arc::new(DeadLetters::open(data_dir).await?);
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(work(receiver, config, client, dead_letters.clone()));
+
+        for path in entries(&dir).await? {
+            sender.send(path).ok();
+        }
+
+        Ok(Arc::new(Self {
+            dir,
+            sender,
+            dead_letters,
+        }))
+    }
+
+    /// Persists a job, returning once it is safely on disk
+    pub async fn push(&self, job: &Job) -> io::Result<()> {
+        let path = self.dir.join(format!("{}.json", unique_name()));
+        write(&path, &serde_json::to_vec(job)?).await?;
+
+        self.sender.send(path).ok();
+        Ok(())
+    }
+}
+
+/// Returns a unique name which sorts in creation order
+pub fn unique_name() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    format!(
+        "{:024}-{:08}",
+        timestamp,
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// Writes a file so that it either fully exists or doesn't exist at all, even after a crash
+pub async fn write(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = fs::File::create(&tmp).await?;
+    file.write_all(contents).await?;
+    file.sync_all().await?;
+    fs::rename(&tmp, path).await
+}
+
+/// Lists the JSON files in a directory in creation order, cleaning up interrupted writes
+pub async fn entries(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => paths.push(path),
+            Some("tmp") => fs::remove_file(&path).await?,
+            _ => (),
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+async fn work(
+    mut receiver: mpsc::UnboundedReceiver<PathBuf>,
+    config: Arc<Config>,
+    client: Arc<Client>,
+    dead_letters: Arc<DeadLetters>,
+) {
+    while let Some(path) = receiver.recv().await {
+        tokio::spawn(deliver(
+            path,
+            config.clone(),
+            client.clone(),
+            dead_letters.clone(),
+        ));
+    }
+}
+
+async fn deliver(
+    path: PathBuf,
+    config: Arc<Config>,
+    client: Arc<Client>,
+    dead_letters: Arc<DeadLetters>,
+) {
+    let job: Job = match fs::read(&path)
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|c| serde_json::from_slice(&c).map_err(|e| e.to_string()))
+    {
+        Ok(j) => j,
+        Err(e) => {
+            eprintln!("Can't read queued job `{}`: {}", path.display(), e);
+            return;
+        }
+    };
+
+    let letter = match config.target(&job.webhook, &job.url) {
+        Some((webhook, target)) => {
+            match forward::send(&client, &job.webhook, webhook, target, &job.payload).await {
+                Ok(()) => None,
+                Err(f) => {
+                    eprintln!("Can't forward `{}` to `{}`: {}", job.webhook, target.url, f);
+                    Some(DeadLetter::new(job, f.to_string(), f.attempts))
+                }
+            }
+        }
+        None => {
+            let error = format!("`{}` has no target `{}`", job.webhook, job.url);
+            eprintln!("Can't deliver queued job `{}`: {}", path.display(), error);
+            Some(DeadLetter::new(job, error, 0))
+        }
+    };
+
+    if let Some(letter) = letter {
+        if let Err(e) = dead_letters.push(&letter).await {
+            eprintln!("Can't store dead letter for `{}`: {}", letter.webhook, e);
+            // Keep the job around so that it is retried on the next start instead of being lost
+            return;
+        }
+    }
+    if let Err(e) = fs::remove_file(&path).await {
+        eprintln!("Can't remove queued job `{}`: {}", path.display(), e);
+    }
+}
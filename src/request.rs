@@ -0,0 +1,103 @@
+use crate::{JsonArray, JsonObject};
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use std::{
+    net::SocketAddr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use warp::{http::HeaderMap, path::Tail, Filter, Rejection};
+
+/// Part of the incoming request a field reads from
+#[derive(Deserialize, Copy, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum Source {
+    #[default]
+    Body,
+    Header,
+    Query,
+    Path,
+    Meta,
+}
+
+/// The incoming request as JSON, so that every part of it can be addressed by paths
+pub struct Request {
+    pub body: JsonValue,
+    /// Header names are lowercase and repeated headers are joined with `, `
+    pub headers: JsonValue,
+    pub query: JsonValue,
+    /// Path segments following the webhook id
+    pub path: JsonValue,
+    pub meta: JsonValue,
+}
+
+impl Request {
+    /// Extracts the webhook id, the raw headers and everything but the body of the request,
+    /// which can only be parsed once it is verified
+    pub fn filter() -> impl Filter<Extract = (String, HeaderMap, Request), Error = Rejection> + Clone
+    {
+        warp::path::param()
+            .and(warp::path::tail())
+            .and(warp::query())
+            .and(warp::addr::remote())
+            .and(warp::header::headers_cloned())
+            .map(
+                |id: String,
+                 tail: Tail,
+                 query: JsonObject,
+                 remote: Option<SocketAddr>,
+                 headers: HeaderMap| {
+                    let mut header_values = JsonObject::new();
+                    for name in headers.keys() {
+                        let values: Vec<&str> = headers
+                            .get_all(name)
+                            .iter()
+                            .filter_map(|v| v.to_str().ok())
+                            .collect();
+                        header_values.insert(name.as_str().to_owned(), values.join(", ").into());
+                    }
+
+                    let path: JsonArray = tail
+                        .as_str()
+                        .split('/')
+                        .filter(|s| !s.is_empty())
+                        .map(JsonValue::from)
+                        .collect();
+
+                    let mut meta = JsonObject::new();
+                    meta.insert("webhookId".to_owned(), id.clone().into());
+                    meta.insert(
+                        "receivedAt".to_owned(),
+                        SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .map(|d| d.as_secs())
+                            .unwrap_or_default()
+                            .into(),
+                    );
+                    meta.insert(
+                        "remoteAddr".to_owned(),
+                        remote.map(|a| a.ip().to_string()).into(),
+                    );
+
+                    let request = Request {
+                        body: JsonValue::Null,
+                        headers: header_values.into(),
+                        query: query.into(),
+                        path: path.into(),
+                        meta: meta.into(),
+                    };
+                    (id, headers, request)
+                },
+            )
+            .untuple_one()
+    }
+
+    pub fn root(&self, source: Source) -> &JsonValue {
+        match source {
+            Source::Body => &self.body,
+            Source::Header => &self.headers,
+            Source::Query => &self.query,
+            Source::Path => &self.path,
+            Source::Meta => &self.meta,
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use crate::{request::Request, JsonObject};
+use log::debug;
+use rhai::{module_resolvers::DummyModuleResolver, Dynamic, Engine, Scope, AST};
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use std::{
+    cell::Cell,
+    convert::TryFrom,
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+thread_local! {
+    /// When the script running on this thread has to stop
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// A Rhai script defining `fn transform(body, headers, meta)`, compiled when the config is loaded
+#[derive(Deserialize)]
+#[serde(try_from = "ScriptConfig")]
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    timeout: Duration,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScriptConfig {
+    Path(PathBuf),
+    #[serde(rename_all = "camelCase")]
+    Options {
+        path: PathBuf,
+        /// Milliseconds
+        #[serde(default = "default_timeout")]
+        timeout: u64,
+        #[serde(default = "default_max_operations")]
+        max_operations: u64,
+        /// Maximum length of strings and number of elements of arrays and maps
+        #[serde(default = "default_max_size")]
+        max_size: usize,
+    },
+}
+
+fn default_timeout() -> u64 {
+    100
+}
+fn default_max_operations() -> u64 {
+    1_000_000
+}
+fn default_max_size() -> usize {
+    100_000
+}
+
+impl TryFrom<ScriptConfig> for Script {
+    type Error = String;
+
+    fn try_from(config: ScriptConfig) -> Result<Self, Self::Error> {
+        let (path, timeout, max_operations, max_size) = match config {
+            ScriptConfig::Path(path) => (
+                path,
+                default_timeout(),
+                default_max_operations(),
+                default_max_size(),
+            ),
+            ScriptConfig::Options {
+                path,
+                timeout,
+                max_operations,
+                max_size,
+            } => (path, timeout, max_operations, max_size),
+        };
+
+        let mut engine = Engine::new();
+        let (print_path, debug_path) = (path.clone(), path.clone());
+        engine
+            .set_module_resolver(DummyModuleResolver::new())
+            .set_max_operations(max_operations)
+            .set_max_string_size(max_size)
+            .set_max_array_size(max_size)
+            .set_max_map_size(max_size)
+            .set_max_call_levels(64)
+            .disable_symbol("eval")
+            .on_print(move |s| debug!("`{}`: {}", print_path.display(), s))
+            .on_debug(move |s, _, position| {
+                debug!("`{}` {}: {}", debug_path.display(), position, s)
+            })
+            .on_progress(|_| {
+                let expired = DEADLINE.with(|d| d.get().is_some_and(|d| Instant::now() > d));
+                if expired {
+                    Some("timeout".into())
+                } else {
+                    None
+                }
+            });
+
+        let code = fs::read_to_string(&path)
+            .map_err(|e| format!("can't read `{}`: {}", path.display(), e))?;
+        let ast = engine
+            .compile(code)
+            .map_err(|e| format!("`{}`: {}", path.display(), e))?;
+        if !ast
+            .iter_functions()
+            .any(|f| f.name == "transform" && f.params.len() == 3)
+        {
+            return Err(format!(
+                "`{}` doesn't define `fn transform(body, headers, meta)`",
+                path.display()
+            ));
+        }
+
+        Ok(Self {
+            engine,
+            ast,
+            timeout: Duration::from_millis(timeout),
+        })
+    }
+}
+
+impl Script {
+    /// Runs the script, returning `None` if it returns `()` to drop the request
+    pub fn run(&self, request: &Request) -> Result<Option<JsonObject>, String> {
+        let arguments = (
+            dynamic(&request.body)?,
+            dynamic(&request.headers)?,
+            dynamic(&request.meta)?,
+        );
+
+        DEADLINE.with(|d| d.set(Some(Instant::now() + self.timeout)));
+        let result =
+            self.engine
+                .call_fn::<Dynamic>(&mut Scope::new(), &self.ast, "transform", arguments);
+        DEADLINE.with(|d| d.set(None));
+
+        let result = result.map_err(|e| e.to_string())?;
+        if result.is_unit() {
+            return Ok(None);
+        }
+        match rhai::serde::from_dynamic(&result).map_err(|e| e.to_string())? {
+            JsonValue::Object(o) => Ok(Some(o)),
+            v => Err(format!("expected an object or `()`, got `{}`", v)),
+        }
+    }
+}
+
+fn dynamic(value: &JsonValue) -> Result<Dynamic, String> {
+    rhai::serde::to_dynamic(value).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn run(name: &str, code: &str) -> Result<Option<JsonObject>, String> {
+        let path = std::env::temp_dir().join(format!("forwardhook-{}.rhai", name));
+        fs::write(&path, code).unwrap();
+        let script: Script = serde_json::from_value(json!(path)).unwrap();
+        let request = Request {
+            body: json!({ "a": 1 }),
+            headers: json!({}),
+            query: json!({}),
+            path: json!([]),
+            meta: json!({}),
+        };
+        let result = script.run(&request);
+        fs::remove_file(path).unwrap();
+        result
+    }
+
+    #[test]
+    fn sandbox() {
+        assert_eq!(
+            run(
+                "print",
+                r#"fn transform(body, headers, meta) { print("body"); debug(body); body }"#
+            ),
+            Ok(json!({ "a": 1 }).as_object().cloned())
+        );
+        // Any file the default resolver could read as a module
+        let module = std::env::temp_dir().join("forwardhook-module.rhai");
+        fs::write(&module, "export const a = 2;").unwrap();
+        let imported = run(
+            "import",
+            &format!(
+                r#"fn transform(body, headers, meta) {{ import {:?} as m; #{{ a: m::a }} }}"#,
+                module
+            ),
+        );
+        fs::remove_file(module).unwrap();
+        assert!(imported.is_err_and(|e| e.contains("forwardhook-module")));
+    }
+}
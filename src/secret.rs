@@ -0,0 +1,37 @@
+use std::{env, fs};
+
+/// Replaces `${env:NAME}` and `${file:PATH}` references with the value of the environment variable
+/// or the contents of the file, `$${` being an escaped `${`
+pub fn interpolate(value: &str) -> Result<String, String> {
+    let mut interpolated = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            interpolated.push_str(&rest[..start - 1]);
+            interpolated.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        interpolated.push_str(&rest[..start]);
+        rest = &rest[start + 2..];
+        let end = rest
+            .find('}')
+            .ok_or_else(|| "unterminated `${`".to_owned())?;
+        interpolated.push_str(&resolve(&rest[..end])?);
+        rest = &rest[end + 1..];
+    }
+    interpolated.push_str(rest);
+    Ok(interpolated)
+}
+
+fn resolve(reference: &str) -> Result<String, String> {
+    match reference.split_once(':') {
+        Some(("env", name)) => env::var(name).map_err(|e| format!("`{}`: {}", name, e)),
+        // Secret files usually end with a newline which isn't part of the secret
+        Some(("file", path)) => fs::read_to_string(path)
+            .map(|s| s.trim_end_matches(&['\r', '\n'][..]).to_owned())
+            .map_err(|e| format!("`{}`: {}", path, e)),
+        _ => Err(format!("unknown reference `${{{}}}`", reference)),
+    }
+}
@@ -0,0 +1,253 @@
+use crate::{
+    request::{Request, Source},
+    resolve, JsonPath, JsonPathSegment,
+};
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use std::{borrow::Cow, convert::TryFrom};
+
+/// A string with `{{ path | filter }}` placeholders, parsed when the config is loaded
+#[derive(Deserialize)]
+#[serde(try_from = "String")]
+pub struct Template(Vec<Part>);
+
+enum Part {
+    Text(String),
+    Placeholder {
+        source: Option<Source>,
+        path: JsonPath,
+        filters: Vec<Filter>,
+    },
+}
+
+enum Filter {
+    Upper,
+    Lower,
+    Truncate(usize),
+    Default(JsonValue),
+    Json,
+}
+
+impl TryFrom<String> for Template {
+    type Error = String;
+
+    fn try_from(template: String) -> Result<Self, Self::Error> {
+        let mut parts = Vec::new();
+        let mut rest = template.as_str();
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_owned()));
+            }
+            let (placeholder, after) = placeholder(&rest[start + 2..])?;
+            parts.push(placeholder);
+            rest = after;
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_owned()));
+        }
+        Ok(Template(parts))
+    }
+}
+
+/// Parses a placeholder following its opening `{{`, returning what follows its closing `}}`
+fn placeholder(s: &str) -> Result<(Part, &str), String> {
+    let s = s.trim_start();
+    let end = s
+        .find(|c: char| c.is_whitespace() || c == '|' || c == '}')
+        .unwrap_or(s.len());
+    let (mut path, mut rest) = s.split_at(end);
+
+    let source = match path.split_once(':') {
+        Some((source, p)) => {
+            path = p;
+            Some(
+                serde_json::from_value(source.into())
+                    .map_err(|_| format!("unknown source `{}`", source))?,
+            )
+        }
+        None => None,
+    };
+    let path = path
+        .split('.')
+        .filter(|s| !s.is_empty())
+        .map(|s| match s.parse() {
+            Ok(i) => JsonPathSegment::Index(i),
+            Err(_) => JsonPathSegment::Key(s.to_owned()),
+        })
+        .collect();
+
+    let mut filters = Vec::new();
+    loop {
+        rest = rest.trim_start();
+        if let Some(after) = rest.strip_prefix("}}") {
+            let placeholder = Part::Placeholder {
+                source,
+                path,
+                filters,
+            };
+            return Ok((placeholder, after));
+        }
+        rest = rest
+            .strip_prefix('|')
+            .ok_or("expected `|` or `}}` in placeholder")?
+            .trim_start();
+
+        let end = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(rest.len());
+        let (name, after) = rest.split_at(end);
+        rest = after.trim_start();
+
+        let argument = match rest.strip_prefix('(') {
+            Some(after) => {
+                // The argument is JSON, which can contain parentheses inside of strings
+                let mut in_string = false;
+                let mut escaped = false;
+                let end = after
+                    .find(|c| {
+                        match c {
+                            _ if escaped => escaped = false,
+                            '\\' if in_string => escaped = true,
+                            '"' => in_string = !in_string,
+                            ')' if !in_string => return true,
+                            _ => (),
+                        }
+                        false
+                    })
+                    .ok_or("expected `)` after filter argument")?;
+                rest = &after[end + 1..];
+                Some(serde_json::from_str(&after[..end]).map_err(|e| e.to_string())?)
+            }
+            None => None,
+        };
+
+        filters.push(match (name, argument) {
+            ("upper", None) => Filter::Upper,
+            ("lower", None) => Filter::Lower,
+            ("json", None) => Filter::Json,
+            ("truncate", Some(JsonValue::Number(n))) => match n.as_u64() {
+                Some(n) => Filter::Truncate(n as usize),
+                None => return Err("`truncate` expects a positive length".to_owned()),
+            },
+            ("default", Some(value)) => Filter::Default(value),
+            (name, _) => return Err(format!("unknown filter or wrong arguments `{}`", name)),
+        });
+    }
+}
+
+impl Template {
+    /// Renders the template, reading paths without an explicit source from `source`
+    pub fn render(&self, request: &Request, source: Source) -> Result<String, String> {
+        let mut rendered = String::new();
+        for part in &self.0 {
+            let (source, path, filters) = match part {
+                Part::Text(text) => {
+                    rendered.push_str(text);
+                    continue;
+                }
+                Part::Placeholder {
+                    source: s,
+                    path,
+                    filters,
+                } => (s.unwrap_or(source), path, filters),
+            };
+
+            let mut value = resolve(request.root(source), path).map(Cow::into_owned);
+            for filter in filters {
+                value = match (filter, value) {
+                    (Filter::Default(default), Err(_))
+                    | (Filter::Default(default), Ok(JsonValue::Null)) => Ok(default.clone()),
+                    (Filter::Default(_), value) | (_, value @ Err(_)) => value,
+                    (Filter::Upper, Ok(v)) => Ok(display(&v).to_uppercase().into()),
+                    (Filter::Lower, Ok(v)) => Ok(display(&v).to_lowercase().into()),
+                    (Filter::Truncate(n), Ok(v)) => {
+                        Ok(display(&v).chars().take(*n).collect::<String>().into())
+                    }
+                    (Filter::Json, Ok(v)) => Ok(v.to_string().into()),
+                };
+            }
+            rendered.push_str(&display(&value?));
+        }
+        Ok(rendered)
+    }
+}
+
+/// Renders strings as is, `null` as an empty string and anything else as JSON
+pub fn display(value: &JsonValue) -> String {
+    match value {
+        JsonValue::String(s) => s.clone(),
+        JsonValue::Null => String::new(),
+        v => v.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rendered(template: &str) -> Result<String, String> {
+        let request = Request {
+            body: json!({ "repo": { "name": "Hook" }, "commits": ["a", "b"], "empty": null }),
+            headers: json!({ "x-event": "push" }),
+            query: json!({}),
+            path: json!([]),
+            meta: json!({}),
+        };
+        Template::try_from(template.to_owned())?.render(&request, Source::Body)
+    }
+
+    #[test]
+    fn placeholders() {
+        assert_eq!(
+            rendered("{{repo.name}} got {{ commits.1 }} on {{header:x-event}}!"),
+            Ok("Hook got b on push!".to_owned())
+        );
+        assert_eq!(rendered("{{}}"), rendered("{{ . }}"));
+        assert_eq!(
+            rendered("no placeholders"),
+            Ok("no placeholders".to_owned())
+        );
+        assert!(rendered("{{ missing }}").is_err());
+        assert_eq!(
+            rendered("{{ repo.name"),
+            Err("expected `|` or `}}` in placeholder".to_owned())
+        );
+        assert_eq!(
+            rendered("{{ cookie:a }}"),
+            Err("unknown source `cookie`".to_owned())
+        );
+    }
+
+    #[test]
+    fn filters() {
+        assert_eq!(
+            rendered("{{ repo.name | upper }} {{ repo.name|lower|truncate(2) }}"),
+            Ok("HOOK ho".to_owned())
+        );
+        assert_eq!(
+            rendered("{{ commits | json }} {{ repo }}"),
+            Ok(r#"["a","b"] {"name":"Hook"}"#.to_owned())
+        );
+        assert_eq!(
+            rendered("{{ missing | default(\"(none)\") }}, {{ empty | default({\"a\": \")\"}) }}"),
+            Ok(r#"(none), {"a":")"}"#.to_owned())
+        );
+        assert_eq!(
+            rendered(r#"{{ missing | default("\")") | upper }}"#),
+            Ok("\")".to_owned())
+        );
+        assert_eq!(
+            rendered("{{ repo | truncate(-1) }}"),
+            Err("`truncate` expects a positive length".to_owned())
+        );
+        assert_eq!(
+            rendered("{{ repo | default(1 }}"),
+            Err("expected `)` after filter argument".to_owned())
+        );
+        assert_eq!(
+            rendered("{{ repo | title }}"),
+            Err("unknown filter or wrong arguments `title`".to_owned())
+        );
+    }
+}
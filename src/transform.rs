@@ -0,0 +1,264 @@
+use crate::{
+    template::display,
+    verify::{Algorithm, Encoding},
+    JsonObject,
+};
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, NaiveDateTime, SecondsFormat, TimeZone, Utc,
+};
+use regex::Regex;
+use serde::{de::Error as _, Deserialize, Deserializer};
+use serde_json::{json, Value as JsonValue};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use std::convert::TryFrom;
+
+/// A step applied to the value of a field, written as an object with a `type` or as a bare name
+/// for steps without options
+#[derive(Deserialize)]
+#[serde(try_from = "JsonValue")]
+pub struct Transform(Kind);
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum Kind {
+    ToString,
+    ToNumber,
+    ToBool,
+    Lowercase,
+    Uppercase,
+    Trim,
+    Truncate {
+        length: usize,
+    },
+    Replace {
+        #[serde(deserialize_with = "regex")]
+        pattern: Regex,
+        with: String,
+    },
+    Extract {
+        #[serde(deserialize_with = "regex")]
+        pattern: Regex,
+        #[serde(default = "default_group")]
+        group: usize,
+    },
+    Split {
+        separator: String,
+    },
+    Join {
+        separator: String,
+    },
+    Lookup {
+        table: JsonObject,
+        default: Option<JsonValue>,
+    },
+    Base64Encode,
+    Base64Decode,
+    Hash {
+        #[serde(default)]
+        algorithm: Algorithm,
+        #[serde(default)]
+        encoding: Encoding,
+    },
+    Timestamp {
+        from: TimeFormat,
+        to: TimeFormat,
+    },
+}
+
+fn default_group() -> usize {
+    1
+}
+
+fn regex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Regex, D::Error> {
+    Regex::new(&String::deserialize(deserializer)?).map_err(D::Error::custom)
+}
+
+#[derive(Deserialize)]
+#[serde(try_from = "String")]
+enum TimeFormat {
+    Rfc3339,
+    Unix,
+    UnixMillis,
+    /// A `strftime` format
+    Custom(String),
+}
+
+impl TryFrom<String> for TimeFormat {
+    type Error = String;
+
+    fn try_from(format: String) -> Result<Self, Self::Error> {
+        match format.as_str() {
+            "rfc3339" => Ok(TimeFormat::Rfc3339),
+            "unix" => Ok(TimeFormat::Unix),
+            "unixMillis" => Ok(TimeFormat::UnixMillis),
+            // Formatting with an invalid specifier panics, so it has to be caught here
+            _ if StrftimeItems::new(&format).any(|i| matches!(i, Item::Error)) => {
+                Err(format!("invalid time format `{}`", format))
+            }
+            _ => Ok(TimeFormat::Custom(format)),
+        }
+    }
+}
+
+impl TryFrom<JsonValue> for Transform {
+    type Error = String;
+
+    fn try_from(value: JsonValue) -> Result<Self, Self::Error> {
+        let value = match value {
+            JsonValue::String(name) => json!({ "type": name }),
+            value => value,
+        };
+        serde_json::from_value(value)
+            .map(Transform)
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl Transform {
+    pub fn apply(&self, value: JsonValue) -> Result<JsonValue, String> {
+        match &self.0 {
+            Kind::ToString => Ok(display(&value).into()),
+            Kind::ToNumber => match &value {
+                JsonValue::Number(_) => Ok(value),
+                JsonValue::Bool(b) => Ok((*b as u8).into()),
+                JsonValue::String(s) => {
+                    let s = s.trim();
+                    s.parse::<i64>()
+                        .map(JsonValue::from)
+                        .ok()
+                        .or_else(|| {
+                            s.parse::<f64>().ok().and_then(|f| {
+                                serde_json::Number::from_f64(f).map(JsonValue::Number)
+                            })
+                        })
+                        .ok_or_else(|| format!("can't convert `{}` to a number", s))
+                }
+                v => Err(format!("can't convert `{}` to a number", v)),
+            },
+            Kind::ToBool => match &value {
+                JsonValue::Bool(_) => Ok(value),
+                JsonValue::Null => Ok(false.into()),
+                JsonValue::Number(n) => Ok((n.as_f64() != Some(0.0)).into()),
+                JsonValue::String(s) => match s.trim().to_lowercase().as_str() {
+                    "true" | "yes" | "on" | "1" => Ok(true.into()),
+                    "false" | "no" | "off" | "0" | "" => Ok(false.into()),
+                    _ => Err(format!("can't convert `{}` to a boolean", s)),
+                },
+                v => Err(format!("can't convert `{}` to a boolean", v)),
+            },
+            Kind::Lowercase => Ok(string(&value)?.to_lowercase().into()),
+            Kind::Uppercase => Ok(string(&value)?.to_uppercase().into()),
+            Kind::Trim => Ok(string(&value)?.trim().into()),
+            Kind::Truncate { length } => Ok(string(&value)?
+                .chars()
+                .take(*length)
+                .collect::<String>()
+                .into()),
+            Kind::Replace { pattern, with } => {
+                Ok(pattern.replace_all(string(&value)?, with.as_str()).into())
+            }
+            Kind::Extract { pattern, group } => {
+                let s = string(&value)?;
+                pattern
+                    .captures(s)
+                    .and_then(|c| c.get(*group))
+                    .map(|m| m.as_str().into())
+                    .ok_or_else(|| format!("`{}` doesn't match `{}`", s, pattern))
+            }
+            Kind::Split { separator } => Ok(string(&value)?
+                .split(separator.as_str())
+                .map(JsonValue::from)
+                .collect()),
+            Kind::Join { separator } => match &value {
+                JsonValue::Array(values) => Ok(values
+                    .iter()
+                    .map(display)
+                    .collect::<Vec<_>>()
+                    .join(separator)
+                    .into()),
+                v => Err(format!("expected an array, got `{}`", v)),
+            },
+            Kind::Lookup { table, default } => {
+                let key = display(&value);
+                table
+                    .get(&key)
+                    .or(default.as_ref())
+                    .cloned()
+                    .ok_or_else(|| format!("`{}` isn't in the lookup table", key))
+            }
+            Kind::Base64Encode => Ok(base64::encode(string(&value)?).into()),
+            Kind::Base64Decode => {
+                let s = string(&value)?;
+                base64::decode(s)
+                    .ok()
+                    .and_then(|d| String::from_utf8(d).ok())
+                    .map(JsonValue::from)
+                    .ok_or_else(|| format!("`{}` isn't base64 encoded text", s))
+            }
+            Kind::Hash {
+                algorithm,
+                encoding,
+            } => {
+                let s = string(&value)?.as_bytes();
+                let digest = match algorithm {
+                    Algorithm::Sha1 => Sha1::digest(s).to_vec(),
+                    Algorithm::Sha256 => Sha256::digest(s).to_vec(),
+                    Algorithm::Sha512 => Sha512::digest(s).to_vec(),
+                };
+                Ok(match encoding {
+                    Encoding::Hex => hex::encode(digest),
+                    Encoding::Base64 => base64::encode(digest),
+                }
+                .into())
+            }
+            Kind::Timestamp { from, to } => Ok(to.format(from.parse(&value)?)),
+        }
+    }
+}
+
+fn string(value: &JsonValue) -> Result<&str, String> {
+    value
+        .as_str()
+        .ok_or_else(|| format!("expected a string, got `{}`", value))
+}
+
+impl TimeFormat {
+    fn parse(&self, value: &JsonValue) -> Result<DateTime<Utc>, String> {
+        let invalid = || format!("`{}` isn't a valid timestamp", value);
+        let integer = || match value {
+            JsonValue::String(s) => s.trim().parse().ok(),
+            v => v.as_i64(),
+        };
+
+        match self {
+            TimeFormat::Rfc3339 => DateTime::parse_from_rfc3339(string(value)?)
+                .map(|t| t.with_timezone(&Utc))
+                .map_err(|_| invalid()),
+            TimeFormat::Unix => integer()
+                .and_then(|t| Utc.timestamp_opt(t, 0).single())
+                .ok_or_else(invalid),
+            TimeFormat::UnixMillis => integer()
+                .and_then(|t| Utc.timestamp_millis_opt(t).single())
+                .ok_or_else(invalid),
+            // Formats without an offset are assumed to be in UTC
+            TimeFormat::Custom(format) => {
+                let s = string(value)?;
+                DateTime::parse_from_str(s, format)
+                    .map(|t| t.with_timezone(&Utc))
+                    .or_else(|_| NaiveDateTime::parse_from_str(s, format).map(|t| t.and_utc()))
+                    .map_err(|_| invalid())
+            }
+        }
+    }
+
+    fn format(&self, time: DateTime<Utc>) -> JsonValue {
+        match self {
+            TimeFormat::Rfc3339 => time.to_rfc3339_opts(SecondsFormat::AutoSi, true).into(),
+            TimeFormat::Unix => time.timestamp().into(),
+            TimeFormat::UnixMillis => time.timestamp_millis().into(),
+            TimeFormat::Custom(format) => time.format(format).to_string().into(),
+        }
+    }
+}
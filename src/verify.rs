@@ -0,0 +1,497 @@
+use crate::StrError;
+use ed25519_dalek::{Signature, VerifyingKey};
+use hmac::{Hmac, Mac};
+use serde::{de::Error as _, Deserialize, Deserializer};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use subtle::ConstantTimeEq;
+use warp::http::HeaderMap;
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Verify {
+    #[serde(rename = "github")]
+    GitHub { secret: String },
+    Stripe {
+        secret: String,
+        #[serde(default = "default_tolerance")]
+        tolerance: u64,
+        #[serde(skip)]
+        seen: ReplayCache,
+    },
+    Slack {
+        secret: String,
+        #[serde(default = "default_tolerance")]
+        tolerance: u64,
+        #[serde(skip)]
+        seen: ReplayCache,
+    },
+    #[serde(rename = "gitlab")]
+    GitLab { token: String },
+    Discord {
+        #[serde(rename = "publicKey", deserialize_with = "public_key")]
+        public_key: VerifyingKey,
+    },
+    Hmac {
+        secret: String,
+        header: String,
+        #[serde(default)]
+        algorithm: Algorithm,
+        #[serde(default)]
+        prefix: String,
+        #[serde(default)]
+        encoding: Encoding,
+    },
+}
+
+fn default_tolerance() -> u64 {
+    300
+}
+
+fn public_key<'de, D: Deserializer<'de>>(deserializer: D) -> Result<VerifyingKey, D::Error> {
+    let key = String::deserialize(deserializer)?;
+    let key = hex::decode(key)
+        .ok()
+        .and_then(|k| k.try_into().ok())
+        .ok_or_else(|| D::Error::custom("public key must be 32 hex-encoded bytes"))?;
+    VerifyingKey::from_bytes(&key).map_err(D::Error::custom)
+}
+
+#[derive(Deserialize, Copy, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Algorithm {
+    Sha1,
+    #[default]
+    Sha256,
+    Sha512,
+}
+
+#[derive(Deserialize, Copy, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    #[default]
+    Hex,
+    Base64,
+}
+
+/// Signatures seen recently, used to refuse replays within the tolerance window
+#[derive(Default)]
+pub struct ReplayCache(Mutex<HashMap<Vec<u8>, u64>>);
+
+impl Verify {
+    pub fn verify(&self, headers: &HeaderMap, body: &[u8]) -> Result<(), StrError> {
+        match self {
+            Verify::GitHub { secret } => {
+                let signature = header(headers, "X-Hub-Signature-256")?;
+                let signature = signature
+                    .strip_prefix("sha256=")
+                    .ok_or(StrError("Malformed signature"))?;
+                let signature = Encoding::Hex.decode(signature)?;
+
+                compare(&hmac(Algorithm::Sha256, secret, &[body]), &signature)
+            }
+            Verify::Stripe {
+                secret,
+                tolerance,
+                seen,
+            } => {
+                let mut timestamp = None;
+                let mut signatures = Vec::new();
+                for pair in header(headers, "Stripe-Signature")?.split(',') {
+                    match pair.split_once('=') {
+                        Some(("t", t)) => timestamp = Some(t),
+                        // Another `v1` signature can still match, for example during secret rotation
+                        Some(("v1", s)) => signatures.extend(Encoding::Hex.decode(s).ok()),
+                        _ => (),
+                    }
+                }
+                let timestamp = timestamp.ok_or(StrError("Missing timestamp"))?;
+
+                let expected = hmac(
+                    Algorithm::Sha256,
+                    secret,
+                    &[timestamp.as_bytes(), b".", body],
+                );
+                signatures
+                    .iter()
+                    .find(|s| compare(&expected, s).is_ok())
+                    .ok_or(StrError("Invalid signature"))?;
+                seen.check(&expected, timestamp, *tolerance)
+            }
+            Verify::Slack {
+                secret,
+                tolerance,
+                seen,
+            } => {
+                let timestamp = header(headers, "X-Slack-Request-Timestamp")?;
+                let signature = header(headers, "X-Slack-Signature")?;
+                let signature = signature
+                    .strip_prefix("v0=")
+                    .ok_or(StrError("Malformed signature"))?;
+                let signature = Encoding::Hex.decode(signature)?;
+
+                let expected = hmac(
+                    Algorithm::Sha256,
+                    secret,
+                    &[b"v0:", timestamp.as_bytes(), b":", body],
+                );
+                compare(&expected, &signature)?;
+                seen.check(&expected, timestamp, *tolerance)
+            }
+            Verify::GitLab { token } => compare(
+                token.as_bytes(),
+                header(headers, "X-Gitlab-Token")?.as_bytes(),
+            ),
+            Verify::Discord { public_key } => {
+                let timestamp = header(headers, "X-Signature-Timestamp")?;
+                let signature = header(headers, "X-Signature-Ed25519")?;
+                let signature = Encoding::Hex
+                    .decode(signature)?
+                    .try_into()
+                    .map_err(|_| StrError("Malformed signature"))?;
+
+                let message = [timestamp.as_bytes(), body].concat();
+                public_key
+                    .verify_strict(&message, &Signature::from_bytes(&signature))
+                    .map_err(|_| StrError("Invalid signature"))
+            }
+            Verify::Hmac {
+                secret,
+                header: name,
+                algorithm,
+                prefix,
+                encoding,
+            } => {
+                let signature = header(headers, name)?;
+                let signature = signature
+                    .strip_prefix(prefix.as_str())
+                    .ok_or(StrError("Malformed signature"))?;
+                let signature = encoding.decode(signature)?;
+
+                compare(&hmac(*algorithm, secret, &[body]), &signature)
+            }
+        }
+    }
+}
+
+impl Encoding {
+    fn decode(self, s: &str) -> Result<Vec<u8>, StrError> {
+        match self {
+            Encoding::Hex => hex::decode(s).map_err(|_| StrError("Malformed signature")),
+            Encoding::Base64 => base64::decode(s).map_err(|_| StrError("Malformed signature")),
+        }
+    }
+}
+
+impl ReplayCache {
+    fn check(&self, signature: &[u8], timestamp: &str, tolerance: u64) -> Result<(), StrError> {
+        let timestamp: u64 = timestamp
+            .parse()
+            .map_err(|_| StrError("Malformed timestamp"))?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        if now.abs_diff(timestamp) > tolerance {
+            return Err(StrError("Timestamp outside of tolerance"));
+        }
+
+        let mut seen = self.0.lock().unwrap();
+        seen.retain(|_, t| now.abs_diff(*t) <= tolerance);
+        match seen.insert(signature.to_vec(), timestamp) {
+            Some(_) => Err(StrError("Replayed request")),
+            None => Ok(()),
+        }
+    }
+}
+
+fn hmac(algorithm: Algorithm, secret: &str, parts: &[&[u8]]) -> Vec<u8> {
+    macro_rules! digest {
+        ($hash:ty) => {{
+            let mut mac = Hmac::<$hash>::new_from_slice(secret.as_bytes()).unwrap();
+            for part in parts {
+                mac.update(part);
+            }
+            mac.finalize().into_bytes().to_vec()
+        }};
+    }
+
+    match algorithm {
+        Algorithm::Sha1 => digest!(Sha1),
+        Algorithm::Sha256 => digest!(Sha256),
+        Algorithm::Sha512 => digest!(Sha512),
+    }
+}
+
+fn compare(expected: &[u8], actual: &[u8]) -> Result<(), StrError> {
+    if bool::from(expected.ct_eq(actual)) {
+        Ok(())
+    } else {
+        Err(StrError("Invalid signature"))
+    }
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Result<&'a str, StrError> {
+    headers
+        .get(name)
+        .ok_or(StrError("Missing signature header"))?
+        .to_str()
+        .map_err(|_| StrError("Malformed signature"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use warp::http::header::HeaderName;
+
+    fn verifier(config: serde_json::Value) -> Verify {
+        serde_json::from_value(config).unwrap()
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(n, v)| (HeaderName::from_static(n), v.parse().unwrap()))
+            .collect()
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn error(result: Result<(), StrError>) -> &'static str {
+        result.unwrap_err().0
+    }
+
+    // https://docs.github.com/en/webhooks/using-webhooks/validating-webhook-deliveries#testing-the-webhook-payload-validation
+    const GITHUB_SIGNATURE: &str =
+        "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+
+    #[test]
+    fn github() {
+        let verify = verifier(json!({ "type": "github", "secret": "It's a Secret to Everybody" }));
+        let valid = headers(&[("x-hub-signature-256", GITHUB_SIGNATURE)]);
+
+        assert!(verify.verify(&valid, b"Hello, World!").is_ok());
+        assert_eq!(
+            error(verify.verify(&valid, b"Hello, World?")),
+            "Invalid signature"
+        );
+        let unprefixed = headers(&[("x-hub-signature-256", &GITHUB_SIGNATURE[7..])]);
+        assert_eq!(
+            error(verify.verify(&unprefixed, b"Hello, World!")),
+            "Malformed signature"
+        );
+        assert_eq!(
+            error(verify.verify(&HeaderMap::new(), b"Hello, World!")),
+            "Missing signature header"
+        );
+    }
+
+    // https://api.slack.com/authentication/verifying-requests-from-slack
+    const SLACK_BODY: &[u8] = b"token=xyzz0WbapA4vBCDEFasx0q6G&team_id=T1DC2JH3J&team_domain=testteamnow&channel_id=G8PSS9T3V&channel_name=foobar&user_id=U2CERLKJA&user_name=roadrunner&command=%2Fwebhook-collect&text=&response_url=https%3A%2F%2Fhooks.slack.com%2Fcommands%2FT1DC2JH3J%2F397700885554%2F96rGlfmibIGlgcZRskXaIFfN&trigger_id=398738663015.47445629121.803a0bc887a14d10d2c447fce8b6703c";
+    const SLACK_SIGNATURE: &str =
+        "v0=a2114d57b48eac39b9ad189dd8316235a7b4a8d21a10bd27519666489c69b503";
+
+    #[test]
+    fn slack() {
+        let secret = "8f742231b10e8888abcd99yyyzzz85a5";
+        let example = headers(&[
+            ("x-slack-request-timestamp", "1531420618"),
+            ("x-slack-signature", SLACK_SIGNATURE),
+        ]);
+
+        // The published example is long past any reasonable tolerance
+        let lenient = verifier(json!({ "type": "slack", "secret": secret, "tolerance": u64::MAX }));
+        assert!(lenient.verify(&example, SLACK_BODY).is_ok());
+        assert_eq!(
+            error(lenient.verify(&example, SLACK_BODY)),
+            "Replayed request"
+        );
+        assert_eq!(
+            error(lenient.verify(&example, &SLACK_BODY[1..])),
+            "Invalid signature"
+        );
+
+        let strict = verifier(json!({ "type": "slack", "secret": secret }));
+        assert_eq!(
+            error(strict.verify(&example, SLACK_BODY)),
+            "Timestamp outside of tolerance"
+        );
+        let unprefixed = headers(&[
+            ("x-slack-request-timestamp", "1531420618"),
+            ("x-slack-signature", &SLACK_SIGNATURE[3..]),
+        ]);
+        assert_eq!(
+            error(strict.verify(&unprefixed, SLACK_BODY)),
+            "Malformed signature"
+        );
+    }
+
+    fn stripe_signature(secret: &str, timestamp: u64, body: &[u8]) -> String {
+        let timestamp = timestamp.to_string();
+        hex::encode(hmac(
+            Algorithm::Sha256,
+            secret,
+            &[timestamp.as_bytes(), b".", body],
+        ))
+    }
+
+    #[test]
+    fn stripe() {
+        let verify = verifier(json!({ "type": "stripe", "secret": "whsec_test" }));
+        let body = br#"{"type":"charge.succeeded"}"#;
+        let t = now();
+        let signature = stripe_signature("whsec_test", t, body);
+
+        let valid = headers(&[("stripe-signature", &format!("t={},v1={}", t, signature))]);
+        assert!(verify.verify(&valid, body).is_ok());
+        assert_eq!(error(verify.verify(&valid, body)), "Replayed request");
+
+        let tampered = headers(&[("stripe-signature", &format!("t={},v1={}", t + 1, signature))]);
+        assert_eq!(error(verify.verify(&tampered, body)), "Invalid signature");
+        assert_eq!(
+            error(verify.verify(&valid, br#"{"type":"charge.failed"}"#)),
+            "Invalid signature"
+        );
+
+        let stale = t - 600;
+        let old = headers(&[(
+            "stripe-signature",
+            &format!(
+                "t={},v1={}",
+                stale,
+                stripe_signature("whsec_test", stale, body)
+            ),
+        )]);
+        assert_eq!(
+            error(verify.verify(&old, body)),
+            "Timestamp outside of tolerance"
+        );
+
+        let untimed = headers(&[("stripe-signature", &format!("v1={}", signature))]);
+        assert_eq!(error(verify.verify(&untimed, body)), "Missing timestamp");
+    }
+
+    #[test]
+    fn stripe_several_signatures() {
+        let verify = verifier(json!({ "type": "stripe", "secret": "whsec_test" }));
+        let body = b"{}";
+        let t = now();
+        let signature = stripe_signature("whsec_test", t, body);
+
+        let rotated = headers(&[(
+            "stripe-signature",
+            &format!("t={},v1={},v1={},v0=ignored", t, "not hex", signature),
+        )]);
+        assert!(verify.verify(&rotated, body).is_ok());
+    }
+
+    #[test]
+    fn gitlab() {
+        let verify = verifier(json!({ "type": "gitlab", "token": "s3cret" }));
+
+        assert!(verify
+            .verify(&headers(&[("x-gitlab-token", "s3cret")]), b"{}")
+            .is_ok());
+        assert_eq!(
+            error(verify.verify(&headers(&[("x-gitlab-token", "s3cre")]), b"{}")),
+            "Invalid signature"
+        );
+        assert_eq!(
+            error(verify.verify(&HeaderMap::new(), b"{}")),
+            "Missing signature header"
+        );
+    }
+
+    // RFC 2202 and RFC 4231, test case 2
+    #[test]
+    fn generic_hmac() {
+        let data = b"what do ya want for nothing?";
+        let cases = [
+            ("sha1", "hex", "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79"),
+            (
+                "sha256",
+                "hex",
+                "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            ),
+            (
+                "sha512",
+                "base64",
+                "Fkt6e/z4GeLjlfvnO1bgo4e9ZCIugx/WECcM1+olBVSXWL91wFqZSm0DT2X48Ob9yuqxo01Ka0tjbgcKOLznNw==",
+            ),
+        ];
+        for (algorithm, encoding, signature) in &cases {
+            let verify = verifier(json!({
+                "type": "hmac",
+                "secret": "Jefe",
+                "header": "x-signature",
+                "algorithm": algorithm,
+                "encoding": encoding,
+                "prefix": "mac=",
+            }));
+            let valid = headers(&[("x-signature", &format!("mac={}", signature))]);
+
+            assert!(verify.verify(&valid, data).is_ok(), "{}", algorithm);
+            assert_eq!(
+                error(verify.verify(&valid, b"what do ya want for something?")),
+                "Invalid signature"
+            );
+            let unprefixed = headers(&[("x-signature", signature)]);
+            assert_eq!(
+                error(verify.verify(&unprefixed, data)),
+                "Malformed signature"
+            );
+        }
+    }
+
+    // RFC 8032, test 2, whose one byte message is split into a timestamp of `r` and an empty body
+    const DISCORD_PUBLIC_KEY: &str =
+        "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c";
+    const DISCORD_SIGNATURE: &str = "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00";
+
+    #[test]
+    fn discord() {
+        let verify = verifier(json!({ "type": "discord", "publicKey": DISCORD_PUBLIC_KEY }));
+        let valid = headers(&[
+            ("x-signature-timestamp", "r"),
+            ("x-signature-ed25519", DISCORD_SIGNATURE),
+        ]);
+
+        assert!(verify.verify(&valid, b"").is_ok());
+        assert_eq!(error(verify.verify(&valid, b"{}")), "Invalid signature");
+        let truncated = headers(&[
+            ("x-signature-timestamp", "r"),
+            ("x-signature-ed25519", &DISCORD_SIGNATURE[2..]),
+        ]);
+        assert_eq!(error(verify.verify(&truncated, b"")), "Malformed signature");
+    }
+
+    #[test]
+    fn replay_cache() {
+        let cache = ReplayCache::default();
+        let t = now().to_string();
+
+        assert!(cache.check(b"a", &t, 300).is_ok());
+        assert!(cache.check(b"b", &t, 300).is_ok());
+        assert_eq!(error(cache.check(b"a", &t, 300)), "Replayed request");
+        assert_eq!(
+            error(cache.check(b"c", &(now() + 600).to_string(), 300)),
+            "Timestamp outside of tolerance"
+        );
+        assert_eq!(
+            error(cache.check(b"c", "yesterday", 300)),
+            "Malformed timestamp"
+        );
+    }
+}
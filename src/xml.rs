@@ -0,0 +1,285 @@
+use crate::{body, template::display, JsonObject};
+use quick_xml::{
+    escape::escape,
+    events::{BytesStart, Event},
+    Reader,
+};
+use serde_json::Value as JsonValue;
+use std::{borrow::Cow, str};
+
+/// Key of the text of elements which also have attributes or children
+const TEXT: &str = "#text";
+/// Prefix of the keys of attributes
+const ATTRIBUTE: char = '@';
+/// Deepest nesting of elements, the same as serde_json allows for JSON bodies
+const MAX_DEPTH: usize = 128;
+
+/// An element being parsed
+struct Element {
+    name: String,
+    children: JsonObject,
+    text: String,
+}
+
+impl Element {
+    fn new(start: &BytesStart) -> Result<Self, String> {
+        let name = str::from_utf8(start.name().as_ref())
+            .map_err(|e| e.to_string())?
+            .to_owned();
+        let mut children = JsonObject::new();
+        for attribute in start.attributes() {
+            let attribute = attribute.map_err(|e| e.to_string())?;
+            let key = str::from_utf8(attribute.key.as_ref()).map_err(|e| e.to_string())?;
+            let value = attribute.unescape_value().map_err(|e| e.to_string())?;
+            children.insert(format!("{}{}", ATTRIBUTE, key), value.into_owned().into());
+        }
+        Ok(Self {
+            name,
+            children,
+            text: String::new(),
+        })
+    }
+
+    /// Elements with only text become strings, others objects
+    fn into_value(mut self) -> JsonValue {
+        if self.children.is_empty() {
+            return self.text.into();
+        }
+        if !self.text.is_empty() {
+            self.children.insert(TEXT.to_owned(), self.text.into());
+        }
+        self.children.into()
+    }
+}
+
+/// Parses an XML document into an object with its root element
+///
+/// Attributes become keys prefixed with `@`, child elements keys with their name, holding an
+/// array when repeated, and text `#text`, unless the element has nothing else and becomes a
+/// string.
+pub fn parse(xml: &[u8]) -> Result<JsonValue, String> {
+    let mut reader = Reader::from_reader(xml);
+    reader.config_mut().trim_text(true);
+
+    let mut document = JsonObject::new();
+    let mut stack: Vec<Element> = Vec::new();
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|e| format!("{} at position {}", e, reader.error_position()))?;
+        let finished = match event {
+            Event::Start(start) => {
+                // Dropping deeply nested values recurses, so they could overflow the stack
+                if stack.len() == MAX_DEPTH {
+                    return Err(format!("elements nested deeper than {}", MAX_DEPTH));
+                }
+                stack.push(Element::new(&start)?);
+                continue;
+            }
+            Event::Empty(start) => Element::new(&start)?,
+            Event::End(_) => stack.pop().ok_or("unexpected closing tag")?,
+            Event::Text(text) => {
+                if let Some(element) = stack.last_mut() {
+                    element
+                        .text
+                        .push_str(&text.unescape().map_err(|e| e.to_string())?);
+                }
+                continue;
+            }
+            Event::CData(data) => {
+                if let Some(element) = stack.last_mut() {
+                    element
+                        .text
+                        .push_str(str::from_utf8(&data).map_err(|e| e.to_string())?);
+                }
+                continue;
+            }
+            Event::Eof => break,
+            _ => continue,
+        };
+
+        let parent = match stack.last_mut() {
+            Some(parent) => &mut parent.children,
+            None => &mut document,
+        };
+        let name = finished.name.clone();
+        body::insert(parent, name, finished.into_value());
+    }
+
+    match stack.last() {
+        Some(element) => Err(format!("unclosed element `{}`", element.name)),
+        None if document.is_empty() => Err("no root element".to_owned()),
+        None => Ok(document.into()),
+    }
+}
+
+/// Renders an object as an XML document, following the conventions of `parse` in the root
+/// element `root`
+pub fn render(object: &JsonObject, root: &str) -> String {
+    let mut xml = String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    element(&mut xml, root, object);
+    xml
+}
+
+fn element(xml: &mut String, name: &str, object: &JsonObject) {
+    let name = sanitize(name);
+    xml.push('<');
+    xml.push_str(&name);
+    for (key, value) in object {
+        if let Some(attribute) = key.strip_prefix(ATTRIBUTE) {
+            xml.push(' ');
+            xml.push_str(&sanitize(attribute));
+            xml.push_str("=\"");
+            xml.push_str(&escape(display(value)));
+            xml.push('"');
+        }
+    }
+    if object.keys().all(|k| k.starts_with(ATTRIBUTE)) {
+        xml.push_str("/>");
+        return;
+    }
+    xml.push('>');
+    for (key, value) in object {
+        match key.as_str() {
+            TEXT => xml.push_str(&escape(display(value))),
+            k if k.starts_with(ATTRIBUTE) => (),
+            k => child(xml, k, value),
+        }
+    }
+    xml.push_str("</");
+    xml.push_str(&name);
+    xml.push('>');
+}
+
+/// Renders a value as elements named `name`, one for each element of arrays
+fn child(xml: &mut String, name: &str, value: &JsonValue) {
+    match value {
+        JsonValue::Array(values) => values.iter().for_each(|v| child(xml, name, v)),
+        JsonValue::Object(object) => element(xml, name, object),
+        JsonValue::Null => element(xml, name, &JsonObject::new()),
+        value => {
+            let name = sanitize(name);
+            xml.push('<');
+            xml.push_str(&name);
+            xml.push('>');
+            xml.push_str(&escape(display(value)));
+            xml.push_str("</");
+            xml.push_str(&name);
+            xml.push('>');
+        }
+    }
+}
+
+/// Replaces the characters which can't appear in element and attribute names with `_`
+fn sanitize(name: &str) -> Cow<'_, str> {
+    let valid = |(i, c): (usize, char)| {
+        c.is_alphanumeric() || c == '_' || c == ':' || (i > 0 && (c == '-' || c == '.'))
+    };
+    let first_digit = name.starts_with(|c: char| c.is_ascii_digit());
+    if !name.is_empty() && !first_digit && name.chars().enumerate().all(valid) {
+        return Cow::Borrowed(name);
+    }
+
+    let mut sanitized: String = name
+        .chars()
+        .enumerate()
+        .map(|(i, c)| if valid((i, c)) { c } else { '_' })
+        .collect();
+    if sanitized.is_empty() || first_digit {
+        sanitized.insert(0, '_');
+    }
+    Cow::Owned(sanitized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parses() {
+        let xml = r#"<?xml version="1.0"?>
+            <!-- comment -->
+            <push repo="a &amp; b">
+                <ref>main</ref>
+                <commit id="1">fix &lt;bug&gt;</commit>
+                <commit id="2"><![CDATA[<raw>]]></commit>
+                <empty/>
+                <tagged kind="x"/>
+            </push>"#;
+        assert_eq!(
+            parse(xml.as_bytes()),
+            Ok(json!({
+                "push": {
+                    "@repo": "a & b",
+                    "ref": "main",
+                    "commit": [
+                        { "@id": "1", "#text": "fix <bug>" },
+                        { "@id": "2", "#text": "<raw>" },
+                    ],
+                    "empty": "",
+                    "tagged": { "@kind": "x" },
+                }
+            }))
+        );
+        assert_eq!(parse(b"<a>text</a>"), Ok(json!({ "a": "text" })));
+        assert!(parse(b"<a><b></a>").is_err_and(|e| e.contains("position")));
+        assert_eq!(parse(b"<a><b>"), Err("unclosed element `b`".to_owned()));
+        assert_eq!(parse(b"  "), Err("no root element".to_owned()));
+    }
+
+    #[test]
+    fn renders() {
+        let object = json!({
+            "@id": 1,
+            "#text": "a < b",
+            "1st name": "x",
+            "items": [{ "@n": true }, "two"],
+            "nested": { "deep": { "#text": "&" } },
+        });
+        assert_eq!(
+            render(object.as_object().unwrap(), "my root"),
+            concat!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+                r#"<my_root id="1">a &lt; b<_1st_name>x</_1st_name>"#,
+                r#"<items n="true"/><items>two</items><nested><deep>&amp;</deep></nested></my_root>"#,
+            )
+        );
+        assert_eq!(
+            render(&JsonObject::new(), "root"),
+            r#"<?xml version="1.0" encoding="UTF-8"?><root/>"#
+        );
+    }
+
+    #[test]
+    fn depth() {
+        let nested = |depth| format!("{}{}", "<a>".repeat(depth), "</a>".repeat(depth));
+        assert!(parse(nested(MAX_DEPTH).as_bytes()).is_ok());
+        assert_eq!(
+            parse(nested(MAX_DEPTH + 1).as_bytes()),
+            Err("elements nested deeper than 128".to_owned())
+        );
+        assert!(parse(nested(200_000).as_bytes()).is_err());
+    }
+
+    #[test]
+    fn keeps_element_order() {
+        let xml = concat!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+            r#"<order id="1"><zone>b</zone><item>x</item><item>y</item><amount>2</amount><note></note></order>"#,
+        );
+        let parsed = parse(xml.as_bytes()).unwrap();
+        let order = parsed["order"].as_object().unwrap();
+        assert_eq!(
+            order.keys().collect::<Vec<_>>(),
+            ["@id", "zone", "item", "amount", "note"]
+        );
+        assert_eq!(render(order, "order"), xml);
+
+        let mapped = json!({ "n": null, "arr": [1, 2] });
+        assert_eq!(
+            render(mapped.as_object().unwrap(), "root"),
+            r#"<?xml version="1.0" encoding="UTF-8"?><root><n/><arr>1</arr><arr>2</arr></root>"#
+        );
+    }
+}